@@ -1,37 +1,39 @@
 use gnu_libjit::{Abi, Context};
 
 fn main() {
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
 
     let float_type = Context::float64_type();
-    let params = vec![float_type];
-    let mut func = context.function(Abi::Cdecl, float_type, params).unwrap();
+    let params = vec![float_type.clone()];
+    let mut func = context.function(Abi::Cdecl, float_type.clone(), params).unwrap();
 
-    let x = func.arg(0).unwrap();
-    let float_ptr_1 = func.alloca(8);
-    let float_ptr_2 = func.alloca(8);
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let float_ptr_1 = func.alloca_of(Context::float64_type(), 1).unwrap();
+        let float_ptr_2 = func.alloca_of(Context::float64_type(), 1).unwrap();
 
-    let const_dbl = func.create_float64_constant(123.0);
-    func.insn_store(&float_ptr_2, &const_dbl);
+        let const_dbl = func.create_float64_constant(123.0);
+        func.insn_store_relative(&float_ptr_2, 0, &const_dbl);
 
-    func.insn_store(&float_ptr_1, &x);
-    let f1 = func.insn_load(&float_ptr_1);
+        func.insn_store_relative(&float_ptr_1, 0, &x);
+        let f1 = func.insn_load_relative(&float_ptr_1, 0, float_type.clone());
 
-    func.insn_store(&float_ptr_1, &f1);
-    let f2 = func.insn_load(&float_ptr_1);
+        func.insn_store_relative(&float_ptr_1, 0, &f1);
+        let f2 = func.insn_load_relative(&float_ptr_1, 0, float_type.clone());
 
-    func.insn_store(&float_ptr_1, &f2);
-    let f3 = func.insn_load(&float_ptr_1);
+        func.insn_store_relative(&float_ptr_1, 0, &f2);
+        let f3 = func.insn_load_relative(&float_ptr_1, 0, float_type.clone());
 
-    let const_dbl2 = func.insn_load(&float_ptr_2);
+        let const_dbl2 = func.insn_load_relative(&float_ptr_2, 0, float_type.clone());
 
-    let x_plus_123 = func.insn_add(&const_dbl2, &f3);
+        let x_plus_123 = func.insn_add(&const_dbl2, &f3);
 
-    func.insn_return(&x_plus_123);
-    func.compile();
+        func.insn_return(&x_plus_123);
+    });
+    func.compile().unwrap();
     context.build_end();
 
-    let result: extern "C" fn(f64) -> f64 = func.to_closure();
+    let result: extern "C" fn(f64) -> f64 = func.to_closure().unwrap();
     assert_eq!(result(1.0), 124.0);
 }
\ No newline at end of file
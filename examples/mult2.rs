@@ -1,13 +1,15 @@
 use gnu_libjit::{Abi, Context};
 
 fn main() {
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
     let mut func = context.function(Abi::Cdecl, Context::float64_type(), vec![]).unwrap();
-    let zero = func.create_float64_constant(0.0);
-    func.insn_return(&zero);
-    func.compile();
+    func.build(|func| {
+        let zero = func.create_float64_constant(0.0);
+        func.insn_return(&zero);
+    });
+    func.compile().unwrap();
     context.build_end();
-    let function: fn() -> f64  = func.to_closure();
+    let function: extern "C" fn() -> f64 = func.to_closure().unwrap();
     println!("{}", function());
 }
@@ -0,0 +1,41 @@
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use gnu_libjit_sys::{jit_function_apply, jit_function_t};
+use libc::c_void;
+use crate::{Context, JitRuntimeError};
+use crate::exception::catch_exceptions;
+use crate::jit_primitive::{JitArgs, JitReturn};
+
+// A compiled function whose signature has been checked against Args and Ret.
+// It borrows the Context so the machine code can't be freed while it is callable.
+pub struct CompiledFn<'ctx, Args: JitArgs, Ret: JitReturn> {
+    function: jit_function_t,
+    _context: PhantomData<&'ctx Context>,
+    _signature: PhantomData<fn(Args) -> Ret>,
+}
+
+impl<'ctx, Args: JitArgs, Ret: JitReturn> CompiledFn<'ctx, Args, Ret> {
+    // function must have exactly the signature (Args) -> Ret.
+    pub(crate) fn new(function: jit_function_t) -> CompiledFn<'ctx, Args, Ret> {
+        CompiledFn { function, _context: PhantomData, _signature: PhantomData }
+    }
+
+    // Call the function with a tuple of arguments eg: add.call((1, 2))
+    // Like try_call it goes through jit_function_apply so a throw always has somewhere to land,
+    // but an exception the function doesn't catch panics. Use try_call to handle them.
+    pub fn call(&self, args: Args) -> Ret {
+        self.try_call(args).unwrap_or_else(|err| panic!("Uncaught exception in jit'ed function: {}", err))
+    }
+
+    // Like call but an exception the function doesn't catch is returned as an Err instead of
+    // unwinding through (and corrupting) the rust stack. Builtin exceptions (division by zero,
+    // checked overflow, ...) become their JitRuntimeError variant instead of exiting the process.
+    pub fn try_call(&self, mut args: Args) -> Result<Ret, JitRuntimeError> {
+        let mut arg_ptrs = args.arg_ptrs();
+        let mut ret = MaybeUninit::<Ret>::uninit();
+        catch_exceptions(|| unsafe {
+            jit_function_apply(self.function, arg_ptrs.as_mut_ptr(), ret.as_mut_ptr() as *mut c_void)
+        })?;
+        Ok(unsafe { ret.assume_init() })
+    }
+}
@@ -1,24 +1,22 @@
-use std::num::TryFromIntError;
-use std::os::raw::c_uint;
-use gnu_libjit_sys::{jit_context_build_end, jit_context_build_start, jit_context_create, jit_context_t, jit_type_long, jit_function_create, jit_type_create_signature, jit_type_float32, jit_type_float64, jit_type_free, jit_type_int, jit_type_t, jit_abi_t, jit_type_sbyte, jit_type_ubyte};
-use crate::{Abi, Function, JitType};
+use std::os::raw::{c_int, c_uint};
+use gnu_libjit_sys::{jit_context_build_end, jit_context_build_start, jit_context_create, jit_context_destroy, jit_context_t, jit_type_long, jit_function_create, jit_type_float32, jit_type_float64, jit_type_free, jit_type_int, jit_type_sbyte, jit_type_ubyte, jit_type_short, jit_type_ushort, jit_type_uint, jit_type_ulong, jit_type_nint, jit_type_nuint, jit_type_nfloat, jit_type_void, jit_type_void_ptr, jit_type_get_kind, JIT_TYPE_VOID, jit_function_t, jit_function_set_on_demand_compiler, JIT_RESULT_OK, JIT_RESULT_COMPILE_ERROR};
+use crate::{Abi, Error, Function, JitType};
+use crate::exception::{emit_catcher, install_builtin_handler};
+use crate::label::check_labels;
+use crate::jit_type::create_signature;
 
+// Owns the libjit context. Every Function (and Value) borrows from it, and everything
+// it built is freed when it is dropped.
 pub struct Context {
     context: jit_context_t,
 }
 
-#[derive(Clone, Debug)]
-pub enum Exception {
-    TooManyParams(TryFromIntError),
-    ParamIndexToLarge(TryFromIntError),
-    ArgIndexTooLarge(String),
-}
-
 impl Context {
     pub fn new() -> Context {
-        unsafe {
-            Context { context: jit_context_create() }
-        }
+        // After creating the context, which initializes libjit
+        let context = unsafe { jit_context_create() };
+        install_builtin_handler();
+        Context { context }
     }
 
     pub fn build_start(&self) {
@@ -33,24 +31,27 @@ impl Context {
     }
 
     // Adds a new function to the context
-    // TODO: This could fail on systems with non-32bit unsigned ints
-    /// let mut context = Context::new();
-    /// let params = vec![JitType::Int]
-    /// let function = context.function(Abi::Cdecl, JitType::Int, params.as_slice());
-    pub fn function(&mut self, abi: Abi, return_type: JitType, params: Vec<JitType>) -> Result<Function, Exception> {
-        let mut params_libjit: Vec<jit_type_t> = params.iter().map(|p| p.inner).collect();
+    /// let context = Context::new();
+    /// let params = vec![Context::int_type()];
+    /// let function = context.function(Abi::Cdecl, Context::int_type(), params)?;
+    pub fn function(&self, abi: Abi, return_type: JitType, params: Vec<JitType>) -> Result<Function<'_>, Error> {
+        c_uint::try_from(params.len()).map_err(Error::TooManyParams)?;
+        if let Some(idx) = params.iter().position(|p| unsafe { jit_type_get_kind(p.inner) } == JIT_TYPE_VOID as i32) {
+            return Err(Error::InvalidSignature(format!("param {} is void", idx)));
+        }
 
         unsafe {
-            let signature = jit_type_create_signature(
-                abi as jit_abi_t,
-                return_type.inner,
-                params_libjit.as_mut_ptr(),
-                params.len() as c_uint,
-                1,
-            );
-            let function = Function::new(jit_function_create(self.context, signature), params);
+            let signature = create_signature(abi, &return_type, &params);
+            if signature.is_null() {
+                return Err(Error::InvalidSignature("libjit could not create the signature".to_string()));
+            }
+            let function = jit_function_create(self.context, signature);
             jit_type_free(signature);
-            Ok(function)
+            if function.is_null() {
+                return Err(Error::InvalidSignature("libjit could not create a function with this signature".to_string()));
+            }
+            jit_function_set_on_demand_compiler(function, Some(compile_on_demand));
+            Ok(Function::new(function, return_type, params))
         }
     }
 
@@ -66,4 +67,35 @@ impl Context {
     pub fn float64_type() -> JitType { unsafe { JitType::new(jit_type_float64) } }
     pub fn sbyte_type() -> JitType { unsafe { JitType::new(jit_type_sbyte) } }
     pub fn ubyte_type() -> JitType { unsafe { JitType::new(jit_type_ubyte) } }
-}
\ No newline at end of file
+    pub fn short_type() -> JitType { unsafe { JitType::new(jit_type_short) } }
+    pub fn ushort_type() -> JitType { unsafe { JitType::new(jit_type_ushort) } }
+    pub fn uint_type() -> JitType { unsafe { JitType::new(jit_type_uint) } }
+    pub fn ulong_type() -> JitType { unsafe { JitType::new(jit_type_ulong) } }
+    // Pointer sized integers
+    pub fn nint_type() -> JitType { unsafe { JitType::new(jit_type_nint) } }
+    pub fn nuint_type() -> JitType { unsafe { JitType::new(jit_type_nuint) } }
+    // The platform's widest float, usually a C long double
+    pub fn nfloat_type() -> JitType { unsafe { JitType::new(jit_type_nfloat) } }
+    pub fn void_ptr_type() -> JitType { unsafe { JitType::new(jit_type_void_ptr) } }
+    pub fn void_type() -> JitType { unsafe { JitType::new(jit_type_void) } }
+}
+
+// Called by libjit the first time a function that hasn't been compiled yet is called (eg: from
+// another function via insn_call). Other than finishing a try_catch's catcher there is nothing
+// to build, returning OK tells libjit to compile the IR already built. Like Function::compile
+// it refuses IR that branches to an unplaced label, libjit raises the error in the caller.
+unsafe extern "C" fn compile_on_demand(function: jit_function_t) -> c_int {
+    if check_labels(function).is_err() {
+        return JIT_RESULT_COMPILE_ERROR;
+    }
+    emit_catcher(function);
+    JIT_RESULT_OK as c_int
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe {
+            jit_context_destroy(self.context);
+        }
+    }
+}
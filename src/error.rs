@@ -0,0 +1,99 @@
+use std::fmt::{Display, Formatter};
+use std::num::TryFromIntError;
+use std::os::raw::c_int;
+
+#[derive(Clone, Debug)]
+pub enum Error {
+    // libjit could not compile the function, usually because the IR was malformed. Holds the
+    // JIT_RESULT_* code jit_compile returned.
+    CompileFailed(c_int),
+    // The signature can't be created eg: a void parameter
+    InvalidSignature(String),
+    // More params than libjit can represent
+    TooManyParams(TryFromIntError),
+    ArgIndexOutOfRange(String),
+    // A rust type didn't match the jit type it was used as
+    TypeMismatch(String),
+    // A struct or union type can't be created eg: duplicate field names
+    InvalidType(String),
+    // No field with this name in the struct
+    UnknownField(String),
+    // A size computed from a count doesn't fit in a usize eg: alloca_of with a huge count
+    SizeOverflow(String),
+    // A label was used as a branch target but never placed with insn_label
+    UnplacedLabel(String),
+    // The function raised an exception it didn't catch
+    Runtime(JitRuntimeError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::CompileFailed(code) => write!(f, "Failed to compile function (libjit result {})", code),
+            Error::InvalidSignature(msg) => write!(f, "Invalid signature: {}", msg),
+            Error::TooManyParams(err) => write!(f, "Too many params: {}", err),
+            Error::ArgIndexOutOfRange(msg) => write!(f, "Arg index out of range: {}", msg),
+            Error::TypeMismatch(msg) => write!(f, "Type mismatch: {}", msg),
+            Error::InvalidType(msg) => write!(f, "Invalid type: {}", msg),
+            Error::UnknownField(name) => write!(f, "Unknown field: {}", name),
+            Error::SizeOverflow(msg) => write!(f, "Size overflow: {}", msg),
+            Error::UnplacedLabel(msg) => write!(f, "Unplaced label: {}", msg),
+            Error::Runtime(err) => write!(f, "Runtime error: {}", err),
+        }
+    }
+}
+
+// Why a call into jit'ed code didn't return normally
+#[derive(Clone, Debug, PartialEq)]
+pub enum JitRuntimeError {
+    // An exception thrown with insn_throw (or by native code via jit_exception_throw) that no
+    // try_catch handled. Holds the address of the thrown object, as an integer so errors can
+    // be sent between threads.
+    Thrown(usize),
+    // libjit's builtin exceptions, raised by checked instructions and the runtime
+    Overflow,
+    Arithmetic,
+    DivisionByZero,
+    CompileError,
+    OutOfMemory,
+    NullReference,
+    NullFunction,
+    CalledNested,
+    OutOfBounds,
+    UndefinedLabel,
+    MemoryFull,
+    // A builtin exception code this crate doesn't know about
+    Unknown(c_int),
+}
+
+impl Display for JitRuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JitRuntimeError::Thrown(object) => write!(f, "Uncaught exception {:#x}", object),
+            JitRuntimeError::Overflow => write!(f, "Overflow during checked arithmetic"),
+            JitRuntimeError::Arithmetic => write!(f, "Arithmetic exception"),
+            JitRuntimeError::DivisionByZero => write!(f, "Division by zero"),
+            JitRuntimeError::CompileError => write!(f, "Error during function compilation"),
+            JitRuntimeError::OutOfMemory => write!(f, "Out of memory"),
+            JitRuntimeError::NullReference => write!(f, "Null pointer dereferenced"),
+            JitRuntimeError::NullFunction => write!(f, "Null function pointer called"),
+            JitRuntimeError::CalledNested => write!(f, "Nested function called from non-nested context"),
+            JitRuntimeError::OutOfBounds => write!(f, "Array index out of bounds"),
+            JitRuntimeError::UndefinedLabel => write!(f, "Undefined label"),
+            JitRuntimeError::MemoryFull => write!(f, "Memory full"),
+            JitRuntimeError::Unknown(code) => write!(f, "Unknown libjit exception {}", code),
+        }
+    }
+}
+
+impl std::error::Error for JitRuntimeError {}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::TooManyParams(err) => Some(err),
+            Error::Runtime(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::os::raw::c_int;
+use std::sync::{Mutex, MutexGuard};
+use gnu_libjit_sys::{jit_exception_get_last_and_clear, jit_exception_set_handler, JIT_RESULT_ARITHMETIC, JIT_RESULT_CALLED_NESTED, JIT_RESULT_COMPILE_ERROR, JIT_RESULT_DIVISION_BY_ZERO, JIT_RESULT_MEMORY_FULL, JIT_RESULT_NULL_FUNCTION, JIT_RESULT_NULL_REFERENCE, JIT_RESULT_OUT_OF_BOUNDS, JIT_RESULT_OUT_OF_MEMORY, JIT_RESULT_OVERFLOW, JIT_RESULT_UNDEFINED_LABEL, jit_function_free_meta, jit_function_get_meta, jit_function_set_meta, jit_function_t, jit_insn_branch, jit_insn_branch_if_pc_not_in_range, jit_insn_default_return, jit_insn_label, jit_insn_rethrow_unhandled, jit_insn_start_catcher, jit_insn_store, jit_insn_thrown_exception, jit_insn_uses_catcher, jit_label_t, jit_type_void_ptr, jit_value_create, jit_value_t};
+use libc::c_void;
+use crate::JitRuntimeError;
+use crate::label::Label;
+
+// Key of the Catcher stored in the function's metadata, libjit reserves keys from 10000 up
+const CATCHER_META: c_int = 1000;
+
+// The try regions of a function. libjit allows a single catcher block per function so it is
+// emitted once, just before compiling, and dispatches to the handler of the innermost try the
+// exception came from. It lives in the function's metadata (not in Function) so that
+// on-demand compilation can emit it too.
+struct Catcher {
+    // Where the catcher puts the thrown exception before jumping to a handler
+    exception: jit_value_t,
+    regions: Vec<TryRegion>,
+}
+
+struct TryRegion {
+    start: jit_label_t,
+    end: jit_label_t,
+    handler: jit_label_t,
+}
+
+// Get the function's catcher, creating it the first time a try is used
+unsafe fn catcher(function: jit_function_t) -> *mut Catcher {
+    let existing = jit_function_get_meta(function, CATCHER_META) as *mut Catcher;
+    if !existing.is_null() {
+        return existing;
+    }
+    jit_insn_uses_catcher(function);
+    let catcher = Box::into_raw(Box::new(Catcher {
+        exception: jit_value_create(function, jit_type_void_ptr),
+        regions: vec![],
+    }));
+    jit_function_set_meta(function, CATCHER_META, catcher as *mut c_void, Some(free_catcher), 1);
+    catcher
+}
+
+// The value the catcher stores the thrown exception in
+pub(crate) unsafe fn exception_slot(function: jit_function_t) -> jit_value_t {
+    (*catcher(function)).exception
+}
+
+// Exceptions thrown between start and end go to handler. Regions must be added innermost first.
+pub(crate) unsafe fn add_try_region(function: jit_function_t, start: jit_label_t, end: jit_label_t, handler: jit_label_t) {
+    (*catcher(function)).regions.push(TryRegion { start, end, handler });
+}
+
+// Emit the catcher block, if the function has any try regions. Must be called once the body
+// is complete and before compiling.
+pub(crate) unsafe fn emit_catcher(function: jit_function_t) {
+    let catcher = jit_function_get_meta(function, CATCHER_META) as *mut Catcher;
+    if catcher.is_null() {
+        return;
+    }
+    let catcher = &mut *catcher;
+    // Don't let the end of the body fall through into the catcher
+    jit_insn_default_return(function);
+    jit_insn_start_catcher(function);
+    jit_insn_store(function, catcher.exception, jit_insn_thrown_exception(function));
+    for region in catcher.regions.iter_mut() {
+        let mut next = Label::new().inner;
+        jit_insn_branch_if_pc_not_in_range(function, region.start, region.end, &mut next);
+        jit_insn_branch(function, &mut region.handler);
+        jit_insn_label(function, &mut next);
+    }
+    jit_insn_rethrow_unhandled(function);
+    jit_function_free_meta(function, CATCHER_META);
+}
+
+unsafe extern "C" fn free_catcher(data: *mut c_void) {
+    drop(Box::from_raw(data as *mut Catcher));
+}
+
+// JIT_RESULT_OVERFLOW is bound as unsigned unlike the other codes
+const OVERFLOW: c_int = JIT_RESULT_OVERFLOW as c_int;
+
+// The builtin exception codes. The object our handler throws for one is the address of its
+// entry here, so they can't be confused with anything thrown by insn_throw.
+static BUILTIN_EXCEPTIONS: [c_int; 11] = [
+    OVERFLOW,
+    JIT_RESULT_ARITHMETIC,
+    JIT_RESULT_DIVISION_BY_ZERO,
+    JIT_RESULT_COMPILE_ERROR,
+    JIT_RESULT_OUT_OF_MEMORY,
+    JIT_RESULT_NULL_REFERENCE,
+    JIT_RESULT_NULL_FUNCTION,
+    JIT_RESULT_CALLED_NESTED,
+    JIT_RESULT_OUT_OF_BOUNDS,
+    JIT_RESULT_UNDEFINED_LABEL,
+    JIT_RESULT_MEMORY_FULL,
+];
+
+// Codes missing from BUILTIN_EXCEPTIONS that libjit has raised. Each is leaked the first time it's
+// seen and its address thrown like an entry of BUILTIN_EXCEPTIONS.
+static UNKNOWN_EXCEPTIONS: Mutex<Vec<&'static c_int>> = Mutex::new(Vec::new());
+
+fn unknown_exceptions() -> MutexGuard<'static, Vec<&'static c_int>> {
+    // Nothing can panic while the lock is held, but this runs under jit'ed code so don't risk it
+    UNKNOWN_EXCEPTIONS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// Called by libjit when jit'ed code raises a builtin exception. Without it libjit prints the
+// error and exits the process, with it the exception is thrown like any other. It must not
+// return null, libjit exits for that too.
+unsafe extern "C" fn builtin_exception_handler(exception_type: c_int) -> *mut c_void {
+    if let Some(code) = BUILTIN_EXCEPTIONS.iter().find(|code| **code == exception_type) {
+        return code as *const c_int as *mut c_void;
+    }
+    let mut unknown = unknown_exceptions();
+    let code = match unknown.iter().copied().find(|code| **code == exception_type) {
+        Some(code) => code,
+        None => {
+            let code: &'static c_int = Box::leak(Box::new(exception_type));
+            unknown.push(code);
+            code
+        }
+    };
+    code as *const c_int as *mut c_void
+}
+
+// Install the builtin handler for the current thread (libjit's handler is per thread). Done when
+// a Context is created, which can't leave its thread, so builtin exceptions can be caught with
+// try_catch however the function is called.
+pub(crate) fn install_builtin_handler() {
+    unsafe { jit_exception_set_handler(Some(builtin_exception_handler)); }
+}
+
+// Runs apply (a jit_function_apply call, which returns 0 if an exception escaped) and turns an
+// escaped exception into an Err
+pub(crate) fn catch_exceptions(apply: impl FnOnce() -> c_int) -> Result<(), JitRuntimeError> {
+    let completed = apply();
+    if completed == 0 {
+        return Err(runtime_error(unsafe { jit_exception_get_last_and_clear() }));
+    }
+    Ok(())
+}
+
+// The error for an exception that escaped a function
+fn runtime_error(thrown: *mut c_void) -> JitRuntimeError {
+    let is_thrown = |code: &&c_int| *code as *const c_int as *mut c_void == thrown;
+    let builtin = BUILTIN_EXCEPTIONS.iter().find(is_thrown).copied()
+        .or_else(|| unknown_exceptions().iter().copied().find(is_thrown).copied());
+    match builtin {
+        Some(OVERFLOW) => JitRuntimeError::Overflow,
+        Some(JIT_RESULT_ARITHMETIC) => JitRuntimeError::Arithmetic,
+        Some(JIT_RESULT_DIVISION_BY_ZERO) => JitRuntimeError::DivisionByZero,
+        Some(JIT_RESULT_COMPILE_ERROR) => JitRuntimeError::CompileError,
+        Some(JIT_RESULT_OUT_OF_MEMORY) => JitRuntimeError::OutOfMemory,
+        Some(JIT_RESULT_NULL_REFERENCE) => JitRuntimeError::NullReference,
+        Some(JIT_RESULT_NULL_FUNCTION) => JitRuntimeError::NullFunction,
+        Some(JIT_RESULT_CALLED_NESTED) => JitRuntimeError::CalledNested,
+        Some(JIT_RESULT_OUT_OF_BOUNDS) => JitRuntimeError::OutOfBounds,
+        Some(JIT_RESULT_UNDEFINED_LABEL) => JitRuntimeError::UndefinedLabel,
+        Some(JIT_RESULT_MEMORY_FULL) => JitRuntimeError::MemoryFull,
+        Some(code) => JitRuntimeError::Unknown(code),
+        None => JitRuntimeError::Thrown(thrown as usize),
+    }
+}
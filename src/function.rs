@@ -1,17 +1,21 @@
 use std::ffi::CString;
-use std::os::raw::c_uint;
-use gnu_libjit_sys::{jit_function_compile, jit_insn_not, jit_insn_ge, jit_insn_le, jit_insn_gt, jit_insn_lt, jit_insn_ne,
-                     jit_insn_and, jit_insn_or, jit_insn_xor, jit_function_t, jit_insn_eq, jit_type_nint, jit_type_int, jit_type_sys_int, jit_type_uint, jit_type_sys_uint, jit_insn_add, jit_insn_div, jit_insn_sub, jit_insn_call_native, jit_insn_mul, jit_insn_return, jit_type_create_signature, jit_type_void, jit_value_create_constant, jit_value_get_param, jit_constant_t, jit_dump_function, jit_abi_t, jit_function_to_closure, jit_insn_branch_if, jit_label_t, jit_insn_label, jit_insn_branch_if_not, jit_type_long, jit_constant_t__bindgen_ty_1, jit_type_sbyte, jit_type_float64, jit_type_ubyte, jit_type_void_ptr, jit_insn_alloca, jit_insn_load, jit_insn_store, jit_value_create_nint_constant, jit_insn_branch};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::os::raw::{c_int, c_uint};
+use gnu_libjit_sys::{jit_type_t, jit_compile, JIT_RESULT_OK, jit_insn_not, jit_insn_ge, jit_insn_le, jit_insn_gt, jit_insn_lt, jit_insn_ne,
+                     jit_insn_and, jit_insn_or, jit_insn_xor, jit_function_t, jit_insn_eq, jit_type_int, jit_type_sys_int, jit_type_uint, jit_type_sys_uint, jit_insn_add, jit_insn_div, jit_insn_sub, jit_insn_call_native, jit_insn_mul, jit_insn_return, jit_type_void, jit_value_create, jit_value_create_constant, jit_value_get_param, jit_constant_t, jit_dump_function, jit_function_to_closure, jit_insn_branch_if, jit_label_t, jit_insn_label, jit_insn_branch_if_not, jit_insn_jump_table, jit_insn_throw, jit_function_apply, jit_function_apply_vararg, jit_function_get_signature, jit_type_get_abi, jit_abi_t, jit_insn_add_ovf, jit_insn_sub_ovf, jit_insn_mul_ovf, jit_insn_check_null, jit_exception_builtin, JIT_RESULT_OUT_OF_BOUNDS, jit_function_reserve_label, jit_type_long, jit_constant_t__bindgen_ty_1, jit_type_sbyte, jit_type_float64, jit_type_ubyte, jit_type_void_ptr, jit_insn_alloca, jit_insn_memcpy, jit_insn_memmove, jit_insn_memset, jit_insn_address_of, jit_insn_load, jit_insn_store, jit_insn_branch, jit_insn_load_relative, jit_insn_store_relative, jit_insn_load_elem, jit_insn_store_elem, jit_insn_rem, jit_insn_rem_ieee, jit_insn_neg, jit_insn_shl, jit_insn_shr, jit_insn_ushr, jit_insn_sshr, jit_insn_min, jit_insn_max, jit_insn_abs, jit_insn_sign, jit_insn_atan2, jit_insn_pow, jit_insn_convert, jit_insn_call, JIT_CALL_TAIL, JIT_CALL_NORETURN, jit_insn_call_indirect, jit_insn_call_indirect_vtable, jit_function_to_vtable_pointer, jit_type_is_signature, jit_type_num_params, jit_value_t, jit_type_short, jit_type_ushort, jit_type_ulong, jit_type_float32, jit_insn_acos, jit_insn_asin, jit_insn_atan, jit_insn_ceil, jit_insn_cos, jit_insn_cosh, jit_insn_exp, jit_insn_floor, jit_insn_log, jit_insn_log10, jit_insn_rint, jit_insn_round, jit_insn_sin, jit_insn_sinh, jit_insn_sqrt, jit_insn_tan, jit_insn_tanh, jit_insn_trunc, jit_insn_is_nan, jit_insn_is_finite, jit_insn_is_inf};
 use libc::c_void;
-use crate::context::Exception;
-use crate::{Abi, JitType};
-use crate::label::Label;
+use crate::{Abi, Context, Error, JitArg, JitType, StructType};
+use crate::compiled_fn::CompiledFn;
+use crate::exception::{add_try_region, catch_exceptions, emit_catcher, exception_slot};
+use crate::jit_primitive::{JitArgs, JitFn, JitPrimitive, JitReturn};
+use crate::label::{check_labels, record_branch_target, record_placed_label, Label, Loop};
 use crate::util::dump;
 use crate::value::Value;
 
 macro_rules! op {
     ($fn_name:ident, $jit_op:ident) => {
-        pub fn $fn_name(&mut self, left: &Value, right: &Value) -> Value {
+        pub fn $fn_name(&mut self, left: &Value<'f>, right: &Value<'f>) -> Value<'f> {
             unsafe {
                 let v = $jit_op(self.function, left.value, right.value);
                 Value::new(v)
@@ -22,7 +26,7 @@ macro_rules! op {
 
 macro_rules! ret_op {
     ($fn_name:ident, $jit_op:ident) => {
-        pub fn $fn_name(&mut self, value:& Value) {
+        pub fn $fn_name(&mut self, value: &Value<'f>) {
             unsafe {
                 $jit_op(self.function, value.value);
             }
@@ -32,7 +36,7 @@ macro_rules! ret_op {
 
 macro_rules! unary_op {
     ($fn_name:ident, $jit_op:ident) => {
-        pub fn $fn_name(&mut self, value: &Value) -> Value {
+        pub fn $fn_name(&mut self, value: &Value<'f>) -> Value<'f> {
             unsafe {
                 let v =$jit_op(self.function, value.value);
                 Value::new(v)
@@ -40,9 +44,28 @@ macro_rules! unary_op {
         }
     }
 }
+macro_rules! mem_op {
+    ($fn_name:ident, $jit_op:ident) => {
+        pub fn $fn_name(&mut self, dest: &Value<'f>, src: &Value<'f>, size: &Value<'f>) {
+            let size = self.insn_convert(size, Context::nuint_type(), false);
+            unsafe {
+                $jit_op(self.function, dest.value, src.value, size.value);
+            }
+        }
+    }
+}
+
+macro_rules! convert_op {
+    ($fn_name:ident, $jit_type:ident) => {
+        pub fn $fn_name(&mut self, value: &Value<'f>) -> Value<'f> {
+            self.insn_convert(value, unsafe { JitType::new($jit_type) }, false)
+        }
+    }
+}
+
 macro_rules! constant_fn {
     ($fn_name:ident, $const_ty:ty, $jit_type:expr, $field:ident) => {
-        pub fn $fn_name(&mut self, constant_value: $const_ty) -> Value {
+        pub fn $fn_name(&mut self, constant_value: $const_ty) -> Value<'f> {
             let type_ = unsafe { $jit_type };
             let const_inner = jit_constant_t__bindgen_ty_1{ $field: constant_value.into()};
             let constant = jit_constant_t {
@@ -59,84 +82,345 @@ macro_rules! constant_fn {
     }
 }
 
-pub struct Function {
+// A function being built in (and owned by) a Context. It cannot outlive the Context.
+pub struct Function<'ctx> {
+    return_type: JitType,
     params: Vec<JitType>,
     function: jit_function_t,
+    // Names of native calls, libjit only borrows them
+    names: Vec<CString>,
+    // Whether insn_check_null and insn_bounds_check emit anything
+    runtime_checks: bool,
+    _context: PhantomData<&'ctx Context>,
 }
 
-impl Function {
+impl<'ctx> Function<'ctx> {
     // Use Context::new().function  to create a new function. This method is private.
-    pub(crate) fn new(function: jit_function_t, params: Vec<JitType>) -> Function {
-        Function { function, params }
-    }
-
-    pub fn compile(&self) {
-        unsafe {
-            if jit_function_compile(self.function) == 0 {
-                panic!("Failed to compile function");
-            }
+    pub(crate) fn new(function: jit_function_t, return_type: JitType, params: Vec<JitType>) -> Function<'ctx> {
+        Function {
+            function,
+            return_type,
+            params,
+            names: vec![],
+            runtime_checks: true,
+            _context: PhantomData,
         }
     }
 
-    pub fn alloca(&self, size: ::std::os::raw::c_long) -> Value {
+    pub fn compile(&self) -> Result<(), Error> {
         unsafe {
-            let bytes = jit_value_create_nint_constant(self.function, jit_type_ubyte, size);
-            Value::new(jit_insn_alloca(self.function, bytes))
+            check_labels(self.function)?;
+            emit_catcher(self.function);
+            let result = jit_compile(self.function);
+            if result != JIT_RESULT_OK as c_int {
+                return Err(Error::CompileFailed(result));
+            }
         }
+        Ok(())
     }
 
     pub fn dump(&self) -> Result<String, std::fmt::Error> {
         dump(|fd| unsafe {
-            jit_dump_function(std::mem::transmute(fd), self.function, "no-name-func".as_ptr() as *const ::std::os::raw::c_char);
+            jit_dump_function(std::mem::transmute(fd), self.function, c"no-name-func".as_ptr());
         })
     }
 
-    // T must be a extern "C" fn() pointer to avoid disaster.
-    // Also don't mess up the arg/return types lest you invite chaos.
-    pub fn to_closure<T>(&self) -> T {
+    // Get a pointer to the function as an extern "C" fn. The fn type is checked against the
+    // signature the function was created with.
+    // eg: let add: extern "C" fn(i32, i32) -> i32 = func.to_closure().unwrap();
+    // The pointer is not tied to the Context, prefer to_compiled_fn which is. Calling it doesn't
+    // set up anywhere for an uncaught exception to land either, use to_compiled_fn for functions
+    // that can throw.
+    pub fn to_closure<F: JitFn>(&self) -> Result<F, Error> {
+        self.check_signature::<F::Args, F::Ret>()?;
         unsafe {
-            let void_ptr = jit_function_to_closure(self.function);
-            std::mem::transmute_copy::<*mut c_void, T>(&void_ptr)
+            Ok(F::from_ptr(jit_function_to_closure(self.function)))
         }
     }
 
-    // Call a native rust function
-    pub fn insn_call_native(&self, native_func: *mut ::std::os::raw::c_void, params: Vec<Value>, return_type: Option<JitType>) -> Value {
-        let c_str = CString::new("native-func").unwrap();
-        let c_str_ptr = c_str.as_ptr();
+    // Like to_closure but the result borrows the Context so it can't outlive the code.
+    // eg: let add = func.to_compiled_fn::<(i32, i32), i32>().unwrap(); add.call((1, 2));
+    pub fn to_compiled_fn<Args: JitArgs, Ret: JitReturn>(&self) -> Result<CompiledFn<'ctx, Args, Ret>, Error> {
+        self.check_signature::<Args, Ret>()?;
+        Ok(CompiledFn::new(self.function))
+    }
+
+    // Call the function with args only known at runtime (eg: in a REPL), checked against its
+    // params. Abi::VarArg functions take extra args after their params. An exception the
+    // function doesn't catch is returned as Error::Runtime, see CompiledFn::try_call.
+    pub fn apply(&self, args: &[JitArg]) -> Result<JitArg, Error> {
+        let is_vararg = self.abi() == Abi::VarArg as jit_abi_t;
+        let count_ok = if is_vararg { args.len() >= self.params.len() } else { args.len() == self.params.len() };
+        if !count_ok {
+            return Err(Error::TypeMismatch(format!("Function takes {} args but was applied to {}", self.params.len(), args.len())));
+        }
+        for (idx, (arg, param)) in args.iter().zip(self.params.iter()).enumerate() {
+            if !arg.jit_type().abi_eq(param) {
+                return Err(Error::TypeMismatch(format!("Arg {} is a {} but the param is a {}", idx, arg.jit_type(), param)));
+            }
+        }
+        if args.contains(&JitArg::Void) {
+            return Err(Error::TypeMismatch("Void can't be passed as an arg".to_string()));
+        }
+        let mut ret = JitArg::zeroed(&self.return_type)
+            .ok_or_else(|| Error::TypeMismatch(format!("Can't apply a function returning {}", self.return_type)))?;
+
+        let mut args = args.to_vec();
+        let mut arg_ptrs: Vec<*mut c_void> = args.iter_mut().map(|arg| arg.as_ptr()).collect();
+        let signature = is_vararg.then(|| {
+            let arg_types: Vec<JitType> = args.iter().map(|arg| arg.jit_type()).collect();
+            JitType::signature(Abi::VarArg, &self.return_type, &arg_types)
+        });
+        let ret_ptr = ret.as_ptr();
+        catch_exceptions(|| unsafe {
+            match &signature {
+                Some(signature) => jit_function_apply_vararg(self.function, signature.inner, arg_ptrs.as_mut_ptr(), ret_ptr),
+                None => jit_function_apply(self.function, arg_ptrs.as_mut_ptr(), ret_ptr),
+            }
+        }).map_err(Error::Runtime)?;
+        Ok(ret)
+    }
+
+    fn check_signature<Args: JitArgs, Ret: JitReturn>(&self) -> Result<(), Error> {
+        let args = Args::jit_types();
+        let ret = Ret::jit_type();
+        let args_match = args.len() == self.params.len() &&
+            args.iter().zip(self.params.iter()).all(|(a, b)| a.abi_eq(b));
+        if !args_match || !ret.abi_eq(&self.return_type) {
+            return Err(Error::TypeMismatch(format!(
+                "Function has signature {:?} -> {:?} but was used as {:?} -> {:?}",
+                self.params, self.return_type, args, ret)));
+        }
+        Ok(())
+    }
+
+    fn abi(&self) -> jit_abi_t {
+        unsafe { jit_type_get_abi(jit_function_get_signature(self.function)) }
+    }
+
+    // Same abi, params and return type once normalized, ie: other can reuse this function's frame
+    fn same_signature(&self, other: &Function<'ctx>) -> bool {
+        self.abi() == other.abi() &&
+            self.params.len() == other.params.len() &&
+            self.params.iter().zip(other.params.iter()).all(|(a, b)| a.abi_eq(b)) &&
+            self.return_type.abi_eq(&other.return_type)
+    }
+
+    // A pointer that calls this function when used with insn_call_indirect_vtable. Like a closure
+    // it compiles the function on demand if needed. Only valid while the Context is alive.
+    pub fn to_vtable_pointer(&self) -> *mut c_void {
+        unsafe { jit_function_to_vtable_pointer(self.function) }
+    }
+
+    // Turn insn_check_null and insn_bounds_check on or off for everything built after this
+    // call. They're on by default, eg: func.set_runtime_checks(cfg!(debug_assertions)) to only
+    // check in debug builds.
+    pub fn set_runtime_checks(&mut self, enabled: bool) {
+        self.runtime_checks = enabled;
+    }
+
+    // Build the IR of the function. The Values made in f are branded with it so using one in
+    // another function (or another build) is a type error, and they can't escape f.
+    // eg: func.build(|func| { let x = func.arg(0)?; func.insn_return(&x); Ok(()) })?;
+    pub fn build<R>(&mut self, f: impl for<'f> FnOnce(&mut FunctionBuilder<'ctx, 'f>) -> R) -> R {
+        f(&mut FunctionBuilder { func: self, _brand: PhantomData })
+    }
+}
+
+// A Function being built, see Function::build. 'f is unique to each build and every Value
+// made through the builder carries it.
+/// ```compile_fail
+/// use gnu_libjit::{Abi, Context};
+/// let context = Context::new();
+/// let mut func_a = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+/// let mut func_b = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+/// func_a.build(|func_a| {
+///     let one = func_a.create_int_constant(1);
+///     func_b.build(|func_b| func_b.insn_return(&one));
+/// });
+/// ```
+pub struct FunctionBuilder<'ctx, 'f> {
+    func: &'f mut Function<'ctx>,
+    // Invariant so a builder's Values can't be passed to another one
+    _brand: PhantomData<fn(&'f ()) -> &'f ()>,
+}
+
+impl<'ctx> Deref for FunctionBuilder<'ctx, '_> {
+    type Target = Function<'ctx>;
+    fn deref(&self) -> &Function<'ctx> {
+        self.func
+    }
+}
+
+impl<'ctx> DerefMut for FunctionBuilder<'ctx, '_> {
+    fn deref_mut(&mut self) -> &mut Function<'ctx> {
+        self.func
+    }
+}
+
+impl<'ctx, 'f> FunctionBuilder<'ctx, 'f> {
+    // Allocates size bytes on the stack, size may be computed at runtime. The memory is freed
+    // when the function returns.
+    pub fn alloca(&mut self, size: &Value<'f>) -> Value<'f> {
+        let size = self.insn_convert(size, Context::nuint_type(), false);
+        unsafe { Value::new(jit_insn_alloca(self.function, size.value)) }
+    }
+
+    // Allocates room on the stack for count values of elem_type
+    pub fn alloca_of(&mut self, elem_type: JitType, count: usize) -> Result<Value<'f>, Error> {
+        let size = elem_type.size().checked_mul(count)
+            .ok_or_else(|| Error::SizeOverflow(format!("{} values of {} don't fit in memory", count, elem_type)))?;
+        let size = self.create_nuint_constant(size);
+        Ok(self.alloca(&size))
+    }
+
+    // A new mutable local (a libjit temporary). Assign to it with insn_store, unlike alloca'd
+    // memory it can live in a register.
+    pub fn create_local(&mut self, value_type: JitType) -> Value<'f> {
+        unsafe { Value::new(jit_value_create(self.function, value_type.inner)) }
+    }
+
+    // Call a native rust function. The signature is built from the types of params, prefer
+    // insn_call_rust which gets it from the rust fn type.
+    pub fn insn_call_native(&self, native_func: *mut ::std::os::raw::c_void, params: Vec<Value<'f>>, return_type: Option<JitType>) -> Value<'f> {
+        self.call_native_with_flags(native_func, params, return_type, 0)
+    }
+
+    fn call_native_with_flags(&self, native_func: *mut ::std::os::raw::c_void, params: Vec<Value<'f>>, return_type: Option<JitType>, flags: c_int) -> Value<'f> {
         let mut sig_args = vec![];
         let mut args = vec![];
         for param in params.iter() {
-            sig_args.push(param.value_type().inner);
+            sig_args.push(param.value_type());
             args.push(param.value);
         }
+        let return_type = return_type.unwrap_or_else(|| unsafe { JitType::new(jit_type_void) });
+        let signature = JitType::signature(Abi::Cdecl, &return_type, &sig_args);
         unsafe {
-            let signature = jit_type_create_signature(
-                Abi::Cdecl as jit_abi_t,
-                if let Some(jtype) = return_type { jtype.inner } else { jit_type_void },
-                sig_args.as_mut_ptr(),
-                params.len() as c_uint,
-                1,
-            );
             Value::new(jit_insn_call_native(self.function,
-                                            c_str_ptr,
+                                            c"native-func".as_ptr(),
                                             native_func,
-                                            signature,
+                                            signature.inner,
                                             args.as_mut_ptr(),
                                             params.len() as c_uint,
-                                            0,
+                                            flags,
             ))
         }
     }
 
+    // Call a rust extern "C" fn. The signature comes from F and each arg is converted to the
+    // matching param type.
+    // eg: func.insn_call_rust::<extern "C" fn(i64, f64) -> i32>(my_fn, vec![a, b])
+    pub fn insn_call_rust<F: JitFn>(&mut self, native_func: F, args: Vec<Value<'f>>) -> Result<Value<'f>, Error> {
+        self.insn_call_rust_named("native-func", native_func, args)
+    }
+
+    // insn_call_rust with the name shown for the call in dumps
+    pub fn insn_call_rust_named<F: JitFn>(&mut self, name: &str, native_func: F, args: Vec<Value<'f>>) -> Result<Value<'f>, Error> {
+        let params = F::Args::jit_types();
+        if args.len() != params.len() {
+            return Err(Error::TypeMismatch(format!("Native function takes {} args but was called with {}", params.len(), args.len())));
+        }
+        let mut arg_values = vec![];
+        for (arg, param) in args.iter().zip(params.iter()) {
+            arg_values.push(self.insn_convert(arg, param.clone(), false).value);
+        }
+        // libjit keeps a pointer to the name so it has to live as long as the function
+        let name = CString::new(name).map_err(|_| Error::InvalidSignature(format!("Call name {:?} contains a nul byte", name)))?;
+        let name_ptr = name.as_ptr();
+        self.names.push(name);
+        let signature = JitType::signature(Abi::Cdecl, &F::Ret::jit_type(), &params);
+        unsafe {
+            Ok(Value::new(jit_insn_call_native(self.function,
+                                               name_ptr,
+                                               native_func.as_ptr(),
+                                               signature.inner,
+                                               arg_values.as_mut_ptr(),
+                                               arg_values.len() as c_uint,
+                                               0,
+            )))
+        }
+    }
+
+    // Call another function from the same context, or this function itself. The callee doesn't
+    // need to be compiled yet, it will be compiled the first time it's called.
+    pub fn insn_call(&self, callee: &Function<'ctx>, args: Vec<Value<'f>>) -> Result<Value<'f>, Error> {
+        self.call_with_flags(callee, args, 0)
+    }
+
+    // Like insn_call but the result must be immediately returned with insn_return. The callee
+    // must have the same signature as this function and no arg may point into this function's
+    // frame. Tail calls to this function itself become a jump.
+    pub fn insn_call_tail(&self, callee: &Function<'ctx>, args: Vec<Value<'f>>) -> Result<Value<'f>, Error> {
+        if !self.same_signature(callee) {
+            return Err(Error::InvalidSignature(format!(
+                "Tail call to {:?} -> {:?} from a function with signature {:?} -> {:?}",
+                callee.params, callee.return_type, self.params, self.return_type)));
+        }
+        self.call_with_flags(callee, args, JIT_CALL_TAIL as c_int)
+    }
+
+    fn call_with_flags(&self, callee: &Function<'ctx>, args: Vec<Value<'f>>, flags: c_int) -> Result<Value<'f>, Error> {
+        if args.len() != callee.params.len() {
+            return Err(Error::TypeMismatch(format!("Function takes {} args but was called with {}", callee.params.len(), args.len())));
+        }
+        let mut arg_values = vec![];
+        for arg in args.iter() {
+            arg_values.push(arg.value);
+        }
+        unsafe {
+            Ok(Value::new(jit_insn_call(self.function,
+                                        c"jit-func".as_ptr(),
+                                        callee.function,
+                                        std::ptr::null_mut(),
+                                        arg_values.as_mut_ptr(),
+                                        arg_values.len() as c_uint,
+                                        flags,
+            )))
+        }
+    }
+
+    // Call the function ptr points to. signature must come from JitType::signature.
+    // Pointers from to_closure, native extern "C" fns and JIT closures all work.
+    pub fn insn_call_indirect(&self, ptr: &Value<'f>, signature: &JitType, args: Vec<Value<'f>>) -> Result<Value<'f>, Error> {
+        self.call_indirect_with(signature, args, |func, signature, args, num_args| unsafe {
+            jit_insn_call_indirect(func, ptr.value, signature, args, num_args, 0)
+        })
+    }
+
+    // Call through a pointer from to_vtable_pointer/create_vtable_pointer_constant.
+    pub fn insn_call_indirect_vtable(&self, ptr: &Value<'f>, signature: &JitType, args: Vec<Value<'f>>) -> Result<Value<'f>, Error> {
+        self.call_indirect_with(signature, args, |func, signature, args, num_args| unsafe {
+            jit_insn_call_indirect_vtable(func, ptr.value, signature, args, num_args, 0)
+        })
+    }
+
+    fn call_indirect_with<F>(&self, signature: &JitType, args: Vec<Value<'f>>, call: F) -> Result<Value<'f>, Error>
+        where F: FnOnce(jit_function_t, jit_type_t, *mut jit_value_t, c_uint) -> jit_value_t {
+        if unsafe { jit_type_is_signature(signature.inner) } == 0 {
+            return Err(Error::InvalidSignature("Indirect calls need a signature type, see JitType::signature".to_string()));
+        }
+        let num_params = unsafe { jit_type_num_params(signature.inner) } as usize;
+        if args.len() != num_params {
+            return Err(Error::TypeMismatch(format!("Signature takes {} args but was called with {}", num_params, args.len())));
+        }
+        let mut arg_values = vec![];
+        for arg in args.iter() {
+            arg_values.push(arg.value);
+        }
+        // libjit keeps its own reference to the signature
+        Ok(Value::new(call(self.function, signature.inner, arg_values.as_mut_ptr(), arg_values.len() as c_uint)))
+    }
+
+    // A constant holding the vtable pointer of callee, for storing in dispatch tables
+    pub fn create_vtable_pointer_constant(&mut self, callee: &Function<'ctx>) -> Value<'f> {
+        self.create_void_ptr_constant(callee.to_vtable_pointer())
+    }
+
     // Get the value of the idx'th arg to the function
-    pub fn arg(&self, idx: i32) -> Result<Value, Exception> {
-        let _arg_type = match self.params.get(idx as usize) {
-            Some(arg_type) => arg_type,
-            None => {
-                return Err(Exception::ArgIndexTooLarge(format!("Function has {} args but you requested index {}", self.params.len(), idx)));
-            }
-        };
+    pub fn arg(&self, idx: i32) -> Result<Value<'f>, Error> {
+        if idx < 0 || idx as usize >= self.params.len() {
+            return Err(Error::ArgIndexOutOfRange(format!("Function has {} args but you requested index {}", self.params.len(), idx)));
+        }
         let value = unsafe {
             jit_value_get_param(self.function, idx as c_uint)
         };
@@ -147,6 +431,11 @@ impl Function {
     op!(insn_add, jit_insn_add);
     op!(insn_div, jit_insn_div);
     op!(insn_sub, jit_insn_sub);
+    // Like insn_add/sub/mult but raise libjit's overflow exception instead of wrapping.
+    // Signedness and width come from the operand types (int, uint, long, ulong).
+    op!(insn_add_checked, jit_insn_add_ovf);
+    op!(insn_sub_checked, jit_insn_sub_ovf);
+    op!(insn_mult_checked, jit_insn_mul_ovf);
     op!(insn_eq, jit_insn_eq);
     op!(insn_and, jit_insn_and);
     op!(insn_or, jit_insn_or);
@@ -156,41 +445,328 @@ impl Function {
     op!(insn_ge, jit_insn_ge);
     op!(insn_gt, jit_insn_gt);
     op!(insn_ne, jit_insn_ne);
+    op!(insn_rem, jit_insn_rem);
+    // Remainder rounding the quotient to the nearest integer (IEEE 754) for floats, same as rem for ints
+    op!(insn_rem_ieee, jit_insn_rem_ieee);
+    op!(insn_min, jit_insn_min);
+    op!(insn_max, jit_insn_max);
+    // Shifts are integer only. shr is arithmetic for signed types and logical for unsigned ones,
+    // ushr is always logical and sshr is always arithmetic.
+    op!(insn_shl, jit_insn_shl);
+    op!(insn_shr, jit_insn_shr);
+    op!(insn_ushr, jit_insn_ushr);
+    op!(insn_sshr, jit_insn_sshr);
+
+    // Math intrinsics. These operate on float32, float64 and nfloat values.
+    op!(insn_atan2, jit_insn_atan2);
+    op!(insn_pow, jit_insn_pow);
 
 
     ret_op!(insn_return, jit_insn_return);
 
     unary_op!(insn_not, jit_insn_not);
+    unary_op!(insn_neg, jit_insn_neg);
+    unary_op!(insn_abs, jit_insn_abs);
+    // -1, 0 or 1 as an int
+    unary_op!(insn_sign, jit_insn_sign);
+
+    // Unary math intrinsics, also float only
+    unary_op!(insn_acos, jit_insn_acos);
+    unary_op!(insn_asin, jit_insn_asin);
+    unary_op!(insn_atan, jit_insn_atan);
+    unary_op!(insn_ceil, jit_insn_ceil);
+    unary_op!(insn_cos, jit_insn_cos);
+    unary_op!(insn_cosh, jit_insn_cosh);
+    unary_op!(insn_exp, jit_insn_exp);
+    unary_op!(insn_floor, jit_insn_floor);
+    unary_op!(insn_log, jit_insn_log);
+    unary_op!(insn_log10, jit_insn_log10);
+    // Half-way cases round to even
+    unary_op!(insn_rint, jit_insn_rint);
+    // Half-way cases round away from zero
+    unary_op!(insn_round, jit_insn_round);
+    unary_op!(insn_sin, jit_insn_sin);
+    unary_op!(insn_sinh, jit_insn_sinh);
+    unary_op!(insn_sqrt, jit_insn_sqrt);
+    unary_op!(insn_tan, jit_insn_tan);
+    unary_op!(insn_tanh, jit_insn_tanh);
+    unary_op!(insn_trunc, jit_insn_trunc);
+    // 1 if the value is NaN, 0 otherwise
+    unary_op!(insn_is_nan, jit_insn_is_nan);
+    // 1 if the value is neither NaN nor infinite, 0 otherwise
+    unary_op!(insn_is_finite, jit_insn_is_finite);
+    // 1 for +inf, -1 for -inf, 0 otherwise
+    unary_op!(insn_is_inf, jit_insn_is_inf);
 
     pub fn insn_branch(&self, label: &mut Label) {
         unsafe { jit_insn_branch(self.function, &mut label.inner as *mut jit_label_t); }
+        self.record_target(label);
     }
 
-    pub fn insn_branch_if(&self, value: &Value, label: &mut Label) {
+    pub fn insn_branch_if(&self, value: &Value<'f>, label: &mut Label) {
         unsafe { jit_insn_branch_if(self.function, value.value, &mut label.inner as *mut jit_label_t); }
+        self.record_target(label);
     }
 
-    pub fn insn_branch_if_not(&self, value: &Value, label: &mut Label) {
+    pub fn insn_branch_if_not(&self, value: &Value<'f>, label: &mut Label) {
         unsafe { jit_insn_branch_if_not(self.function, value.value, &mut label.inner as *mut jit_label_t); }
+        self.record_target(label);
+    }
+
+    fn record_target(&self, label: &Label) {
+        unsafe { record_branch_target(self.function, label) };
+    }
+
+    // Runs then only when cond is non zero
+    pub fn if_then(&mut self, cond: &Value<'f>, then: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>)) {
+        let mut end = Label::new();
+        self.insn_branch_if_not(cond, &mut end);
+        then(self);
+        self.insn_label(&mut end);
     }
 
-    pub fn insn_load(&mut self, ptr: &Value) -> Value {
+    // Runs then when cond is non zero and otherwise when it is zero. Use a local from
+    // create_local to get a result out of both arms.
+    pub fn if_else(&mut self, cond: &Value<'f>, then: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>), otherwise: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>)) {
+        let mut else_label = Label::new();
+        let mut end = Label::new();
+        self.insn_branch_if_not(cond, &mut else_label);
+        then(self);
+        self.insn_branch(&mut end);
+        self.insn_label(&mut else_label);
+        otherwise(self);
+        self.insn_label(&mut end);
+    }
+
+    // Builds cond, then body, repeating while cond is non zero. body gets a Loop for break_
+    // and continue_, continue_ re-checks cond.
+    pub fn while_loop(&mut self, cond: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>) -> Value<'f>, body: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>, Loop<'f>)) {
+        let lp = self.new_loop();
+        self.insn_label(&mut lp.continue_label());
+        let cond = cond(self);
+        self.insn_branch_if_not(&cond, &mut lp.break_label());
+        body(self, lp);
+        self.insn_branch(&mut lp.continue_label());
+        self.insn_label(&mut lp.break_label());
+    }
+
+    // Repeats body until it calls break_ (or returns)
+    pub fn loop_(&mut self, body: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>, Loop<'f>)) {
+        let lp = self.new_loop();
+        self.insn_label(&mut lp.continue_label());
+        body(self, lp);
+        self.insn_branch(&mut lp.continue_label());
+        self.insn_label(&mut lp.break_label());
+    }
+
+    // Jump to just after the loop
+    pub fn break_(&mut self, lp: &Loop<'f>) {
+        self.insn_branch(&mut lp.break_label());
+    }
+
+    // Jump to the start of the next iteration
+    pub fn continue_(&mut self, lp: &Loop<'f>) {
+        self.insn_branch(&mut lp.continue_label());
+    }
+
+    // Runs body, if it (or anything it calls) throws, runs handler with the thrown object.
+    // Exceptions thrown by handler propagate to the enclosing try_catch or the caller.
+    // The catch dispatch is emitted when the function is compiled so build the whole body
+    // before calling compile.
+    pub fn try_catch(&mut self, body: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>), handler: impl FnOnce(&mut FunctionBuilder<'ctx, 'f>, Value<'f>)) {
+        let slot = unsafe { exception_slot(self.function) };
+        let mut start = Label::new();
+        let mut end = Label::new();
+        let mut handler_label = Label::new();
+        let mut done = Label::new();
+        self.insn_label(&mut start);
+        body(self);
+        self.insn_label(&mut end);
+        self.insn_branch(&mut done);
+        self.insn_label(&mut handler_label);
+        // Nested try_catches share the slot so give the handler its own copy
+        let exception = self.create_local(Context::void_ptr_type());
+        self.insn_store(&exception, &Value::new(slot));
+        handler(self, exception);
+        self.insn_label(&mut done);
+        unsafe { add_try_region(self.function, start.inner, end.inner, handler_label.inner) };
+    }
+
+    // Throw value (a pointer) as an exception. It goes to the innermost enclosing try_catch,
+    // or out to the caller where CompiledFn::try_call returns it as JitRuntimeError::Thrown.
+    pub fn insn_throw(&mut self, value: &Value<'f>) {
+        let value = self.insn_convert(value, Context::void_ptr_type(), false);
+        unsafe { jit_insn_throw(self.function, value.value); }
+    }
+
+    // Raise libjit's null reference exception if value is null
+    pub fn insn_check_null(&mut self, value: &Value<'f>) {
+        if self.runtime_checks {
+            unsafe { jit_insn_check_null(self.function, value.value); }
+        }
+    }
+
+    // Raise libjit's out of bounds exception unless 0 <= index < len. Like every builtin
+    // exception it can be caught with try_catch (Context::new installs the handler that makes
+    // them throwable) or surfaces from CompiledFn::try_call.
+    pub fn insn_bounds_check(&mut self, index: &Value<'f>, len: &Value<'f>) {
+        if !self.runtime_checks {
+            return;
+        }
+        let index = self.insn_convert(index, Context::nint_type(), false);
+        let len = self.insn_convert(len, Context::nint_type(), false);
+        let zero = self.create_constant(0isize);
+        let not_negative = self.insn_ge(&index, &zero);
+        let below_len = self.insn_lt(&index, &len);
+        let in_bounds = self.insn_and(&not_negative, &below_len);
+        let mut ok = Label::new();
+        self.insn_branch_if(&in_bounds, &mut ok);
+        let code = self.create_int_constant(JIT_RESULT_OUT_OF_BOUNDS);
+        // jit_exception_builtin never returns, so libjit needn't keep anything live past it
+        self.call_native_with_flags(jit_exception_builtin as *mut c_void, vec![code], None, JIT_CALL_NORETURN as c_int);
+        self.insn_label(&mut ok);
+    }
+
+    // Loop labels are reserved up front so Loop can be copied into nested closures
+    fn new_loop(&self) -> Loop<'f> {
         unsafe {
-            let value = jit_insn_load(self.function, ptr.value);
-            // let value_type = jit_value_get_type(value);
+            Loop::new(jit_function_reserve_label(self.function), jit_function_reserve_label(self.function))
+        }
+    }
+
+    // Branch to labels[value], or to fallthrough when value is negative or >= labels.len().
+    // Cheaper than a chain of insn_eq/insn_branch_if for a switch with many arms.
+    pub fn insn_jump_table(&mut self, value: &Value<'f>, labels: &mut [Label], fallthrough: &mut Label) {
+        if !labels.is_empty() {
+            // libjit fills in any labels not yet allocated so they have to be copied back
+            let mut inner: Vec<jit_label_t> = labels.iter().map(|label| label.inner).collect();
+            unsafe {
+                jit_insn_jump_table(self.function, value.value, inner.as_mut_ptr(), inner.len() as c_uint);
+            }
+            for (label, inner) in labels.iter_mut().zip(inner) {
+                label.inner = inner;
+                self.record_target(label);
+            }
+        }
+        self.insn_branch(fallthrough);
+    }
+
+    // Convert value to target_type. Floats are truncated towards zero when converted to ints.
+    // With check_overflow set a value that doesn't fit in target_type raises libjit's overflow
+    // exception when the code runs, otherwise it is silently wrapped.
+    pub fn insn_convert(&mut self, value: &Value<'f>, target_type: JitType, check_overflow: bool) -> Value<'f> {
+        unsafe {
+            Value::new(jit_insn_convert(self.function, value.value, target_type.inner, check_overflow as c_int))
+        }
+    }
+
+    // Unchecked conversions, see insn_convert
+    convert_op!(insn_to_sbyte, jit_type_sbyte);
+    convert_op!(insn_to_ubyte, jit_type_ubyte);
+    convert_op!(insn_to_short, jit_type_short);
+    convert_op!(insn_to_ushort, jit_type_ushort);
+    convert_op!(insn_to_int, jit_type_int);
+    convert_op!(insn_to_uint, jit_type_uint);
+    convert_op!(insn_to_long, jit_type_long);
+    convert_op!(insn_to_ulong, jit_type_ulong);
+    convert_op!(insn_to_float32, jit_type_float32);
+    convert_op!(insn_to_float64, jit_type_float64);
+
+    // Copies the value into a new temporary. This does not dereference anything,
+    // use insn_load_relative to read memory through a pointer.
+    pub fn insn_load(&mut self, value: &Value<'f>) -> Value<'f> {
+        unsafe {
+            let value = jit_insn_load(self.function, value.value);
             Value::new(value)
         }
     }
 
-    pub fn insn_store(&mut self, ptr: &Value, value: &Value) {
+    // Assigns value to dest. This does not write through a pointer,
+    // use insn_store_relative to write memory.
+    pub fn insn_store(&mut self, dest: &Value<'f>, value: &Value<'f>) {
+        unsafe {
+            jit_insn_store(self.function, dest.value, value.value);
+        }
+    }
+
+    // A pointer to value, which is made addressable. Lets a native function write to a local.
+    pub fn insn_address_of(&mut self, value: &Value<'f>) -> Value<'f> {
+        unsafe { Value::new(jit_insn_address_of(self.function, value.value)) }
+    }
+
+    // Load a value of type value_type from the address ptr + offset (in bytes)
+    pub fn insn_load_relative(&mut self, ptr: &Value<'f>, offset: ::std::os::raw::c_long, value_type: JitType) -> Value<'f> {
+        unsafe {
+            Value::new(jit_insn_load_relative(self.function, ptr.value, offset, value_type.inner))
+        }
+    }
+
+    // Store value at the address ptr + offset (in bytes)
+    pub fn insn_store_relative(&mut self, ptr: &Value<'f>, offset: ::std::os::raw::c_long, value: &Value<'f>) {
+        unsafe {
+            jit_insn_store_relative(self.function, ptr.value, offset, value.value);
+        }
+    }
+
+    // Load the named field of the struct ptr points to
+    pub fn insn_load_field(&mut self, ptr: &Value<'f>, struct_type: &StructType, field: &str) -> Result<Value<'f>, Error> {
+        let (offset, field_type) = struct_type.field(field)?;
+        Ok(self.insn_load_relative(ptr, offset as ::std::os::raw::c_long, field_type))
+    }
+
+    // Store value into the named field of the struct ptr points to. value is converted to the
+    // field's type.
+    pub fn insn_store_field(&mut self, ptr: &Value<'f>, struct_type: &StructType, field: &str, value: &Value<'f>) -> Result<(), Error> {
+        let (offset, field_type) = struct_type.field(field)?;
+        let value = self.insn_convert(value, field_type, false);
+        self.insn_store_relative(ptr, offset as ::std::os::raw::c_long, &value);
+        Ok(())
+    }
+
+    // Load element number index from an array of elem_type starting at base_ptr
+    pub fn insn_load_elem(&mut self, base_ptr: &Value<'f>, index: &Value<'f>, elem_type: JitType) -> Value<'f> {
+        unsafe {
+            Value::new(jit_insn_load_elem(self.function, base_ptr.value, index.value, elem_type.inner))
+        }
+    }
+
+    // Store value as element number index of the array starting at base_ptr. The element
+    // type is the type of value.
+    pub fn insn_store_elem(&mut self, base_ptr: &Value<'f>, index: &Value<'f>, value: &Value<'f>) {
         unsafe {
-            jit_insn_store(self.function, ptr.value, value.value);
+            jit_insn_store_elem(self.function, base_ptr.value, index.value, value.value);
         }
     }
 
+    // Copy size bytes from the memory src points to into dest, the regions must not overlap
+    mem_op!(insn_memcpy, jit_insn_memcpy);
+    // Copy size bytes from src to dest, the regions may overlap
+    mem_op!(insn_memmove, jit_insn_memmove);
+    // Set size bytes starting at dest to the byte value
+    pub fn insn_memset(&mut self, dest: &Value<'f>, value: &Value<'f>, size: &Value<'f>) {
+        let size = self.insn_convert(size, Context::nuint_type(), false);
+        unsafe {
+            jit_insn_memset(self.function, dest.value, value.value, size.value);
+        }
+    }
+
+    // Copy the struct src points to into the struct dest points to
+    pub fn insn_copy_struct(&mut self, dest: &Value<'f>, src: &Value<'f>, struct_type: &StructType) {
+        let size = self.create_nuint_constant(struct_type.size());
+        self.insn_memcpy(dest, src, &size);
+    }
+
+    // Zero every field (and any padding) of the struct ptr points to
+    pub fn insn_zero_struct(&mut self, ptr: &Value<'f>, struct_type: &StructType) {
+        let zero = self.create_int_constant(0);
+        let size = self.create_nuint_constant(struct_type.size());
+        self.insn_memset(ptr, &zero, &size);
+    }
+
     pub fn insn_label(&self, label: &mut Label) {
         let lbl_ptr = (&mut label.inner) as *mut jit_label_t;
         unsafe { jit_insn_label(self.function, lbl_ptr); }
+        unsafe { record_placed_label(self.function, label.inner) };
     }
 
     constant_fn!(create_float64_constant, ::std::os::raw::c_double, jit_type_float64, float64_value);
@@ -203,5 +779,32 @@ impl Function {
     constant_fn!(create_ubyte_constant, ::std::os::raw::c_uchar, jit_type_ubyte, uint_value);
     constant_fn!(create_sbyte_constant, ::std::os::raw::c_char, jit_type_sbyte, int_value);
     constant_fn!(create_void_ptr_constant, *mut ::std::os::raw::c_void, jit_type_void_ptr, ptr_value);
-    constant_fn!(create_nint_constant, ::std::os::raw::c_int, jit_type_nint, int_value);
+    constant_fn!(create_float32_constant, f32, jit_type_float32, float32_value);
+    constant_fn!(create_short_constant, ::std::os::raw::c_short, jit_type_short, int_value);
+    constant_fn!(create_ushort_constant, ::std::os::raw::c_ushort, jit_type_ushort, int_value);
+    constant_fn!(create_ulong_constant, ::std::os::raw::c_ulong, jit_type_ulong, ulong_value);
+
+    pub fn create_nint_constant(&mut self, constant_value: isize) -> Value<'f> {
+        self.create_constant(constant_value)
+    }
+
+    pub fn create_nuint_constant(&mut self, constant_value: usize) -> Value<'f> {
+        self.create_constant(constant_value)
+    }
+
+    // Rust has no long double so the constant is built from an f64 and widened
+    pub fn create_nfloat_constant(&mut self, constant_value: f64) -> Value<'f> {
+        let value = self.create_float64_constant(constant_value);
+        self.insn_convert(&value, Context::nfloat_type(), false)
+    }
+
+    // A constant of T's libjit type eg: create_constant(1u16) is a ushort
+    pub fn create_constant<T: JitPrimitive>(&mut self, constant_value: T) -> Value<'f> {
+        let constant = jit_constant_t {
+            type_: T::jit_type().inner,
+            __bindgen_padding_0: 0,
+            un: constant_value.to_constant(),
+        };
+        Value::new(unsafe { jit_value_create_constant(self.function, &constant as *const jit_constant_t) })
+    }
 }
@@ -0,0 +1,114 @@
+use libc::c_void;
+use crate::{Context, JitPrimitive, JitType, TypeKind};
+
+// A primitive value whose type is only known at runtime, the argument and return type of
+// Function::apply.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JitArg {
+    SByte(i8),
+    UByte(u8),
+    Short(i16),
+    UShort(u16),
+    Int(i32),
+    UInt(u32),
+    Long(i64),
+    ULong(u64),
+    NInt(isize),
+    NUInt(usize),
+    Float32(f32),
+    Float64(f64),
+    Ptr(*mut c_void),
+    // The result of a function returning void
+    Void,
+}
+
+impl JitArg {
+    pub fn jit_type(&self) -> JitType {
+        match self {
+            JitArg::SByte(_) => i8::jit_type(),
+            JitArg::UByte(_) => u8::jit_type(),
+            JitArg::Short(_) => i16::jit_type(),
+            JitArg::UShort(_) => u16::jit_type(),
+            JitArg::Int(_) => i32::jit_type(),
+            JitArg::UInt(_) => u32::jit_type(),
+            JitArg::Long(_) => i64::jit_type(),
+            JitArg::ULong(_) => u64::jit_type(),
+            JitArg::NInt(_) => isize::jit_type(),
+            JitArg::NUInt(_) => usize::jit_type(),
+            JitArg::Float32(_) => f32::jit_type(),
+            JitArg::Float64(_) => f64::jit_type(),
+            JitArg::Ptr(_) => <*mut c_void>::jit_type(),
+            JitArg::Void => Context::void_type(),
+        }
+    }
+
+    // A pointer to the value, the form jit_function_apply takes args in
+    pub(crate) fn as_ptr(&mut self) -> *mut c_void {
+        match self {
+            JitArg::SByte(v) => v as *mut i8 as *mut c_void,
+            JitArg::UByte(v) => v as *mut u8 as *mut c_void,
+            JitArg::Short(v) => v as *mut i16 as *mut c_void,
+            JitArg::UShort(v) => v as *mut u16 as *mut c_void,
+            JitArg::Int(v) => v as *mut i32 as *mut c_void,
+            JitArg::UInt(v) => v as *mut u32 as *mut c_void,
+            JitArg::Long(v) => v as *mut i64 as *mut c_void,
+            JitArg::ULong(v) => v as *mut u64 as *mut c_void,
+            JitArg::NInt(v) => v as *mut isize as *mut c_void,
+            JitArg::NUInt(v) => v as *mut usize as *mut c_void,
+            JitArg::Float32(v) => v as *mut f32 as *mut c_void,
+            JitArg::Float64(v) => v as *mut f64 as *mut c_void,
+            JitArg::Ptr(v) => v as *mut *mut c_void as *mut c_void,
+            JitArg::Void => std::ptr::null_mut(),
+        }
+    }
+
+    // A zeroed value of the given type for libjit to write a return value into, None if the
+    // type can't be represented (structs, nfloat)
+    pub(crate) fn zeroed(jit_type: &JitType) -> Option<JitArg> {
+        let kind = match jit_type.kind() {
+            TypeKind::Tagged(_) => jit_type.untagged()?.kind(),
+            kind => kind,
+        };
+        Some(match kind {
+            TypeKind::Void => JitArg::Void,
+            TypeKind::SByte => JitArg::SByte(0),
+            TypeKind::UByte => JitArg::UByte(0),
+            TypeKind::Short => JitArg::Short(0),
+            TypeKind::UShort => JitArg::UShort(0),
+            TypeKind::Int => JitArg::Int(0),
+            TypeKind::UInt => JitArg::UInt(0),
+            TypeKind::Long => JitArg::Long(0),
+            TypeKind::ULong => JitArg::ULong(0),
+            TypeKind::NInt => JitArg::NInt(0),
+            TypeKind::NUInt => JitArg::NUInt(0),
+            TypeKind::Float32 => JitArg::Float32(0.0),
+            TypeKind::Float64 => JitArg::Float64(0.0),
+            TypeKind::Ptr | TypeKind::Signature => JitArg::Ptr(std::ptr::null_mut()),
+            _ => return None,
+        })
+    }
+}
+
+macro_rules! from_primitive {
+    ($rust_ty:ty, $variant:ident) => {
+        impl From<$rust_ty> for JitArg {
+            fn from(value: $rust_ty) -> JitArg {
+                JitArg::$variant(value)
+            }
+        }
+    }
+}
+
+from_primitive!(i8, SByte);
+from_primitive!(u8, UByte);
+from_primitive!(i16, Short);
+from_primitive!(u16, UShort);
+from_primitive!(i32, Int);
+from_primitive!(u32, UInt);
+from_primitive!(i64, Long);
+from_primitive!(u64, ULong);
+from_primitive!(isize, NInt);
+from_primitive!(usize, NUInt);
+from_primitive!(f32, Float32);
+from_primitive!(f64, Float64);
+from_primitive!(*mut c_void, Ptr);
@@ -0,0 +1,150 @@
+use gnu_libjit_sys::{jit_constant_t__bindgen_ty_1, jit_type_float32, jit_type_float64, jit_type_int, jit_type_long, jit_type_nint, jit_type_nuint, jit_type_sbyte, jit_type_short, jit_type_ubyte, jit_type_uint, jit_type_ulong, jit_type_ushort, jit_type_void, jit_type_void_ptr};
+use libc::c_void;
+use crate::JitType;
+
+pub(crate) mod sealed {
+    use gnu_libjit_sys::jit_constant_t__bindgen_ty_1;
+
+    // Keeps JitPrimitive to the types below, libjit only understands those
+    pub trait Sealed {
+        // The value as a libjit constant of its jit_type(), used by Function::create_constant
+        fn to_constant(self) -> jit_constant_t__bindgen_ty_1;
+    }
+}
+
+use sealed::Sealed;
+
+// A rust type with a libjit equivalent that can be passed to and from jit'ed code.
+pub trait JitPrimitive: Copy + Sealed {
+    fn jit_type() -> JitType;
+}
+
+// A rust type that a jit'ed function may return. Every primitive plus () for void.
+pub trait JitReturn {
+    fn jit_type() -> JitType;
+}
+
+macro_rules! primitive {
+    ($rust_ty:ty, $jit_type:ident, $field:ident) => {
+        impl JitPrimitive for $rust_ty {
+            fn jit_type() -> JitType {
+                unsafe { JitType::new($jit_type) }
+            }
+        }
+
+        impl Sealed for $rust_ty {
+            fn to_constant(self) -> jit_constant_t__bindgen_ty_1 {
+                jit_constant_t__bindgen_ty_1 { $field: self as _ }
+            }
+        }
+    }
+}
+
+primitive!(i8, jit_type_sbyte, int_value);
+primitive!(u8, jit_type_ubyte, int_value);
+primitive!(i16, jit_type_short, int_value);
+primitive!(u16, jit_type_ushort, int_value);
+primitive!(i32, jit_type_int, int_value);
+primitive!(u32, jit_type_uint, uint_value);
+primitive!(i64, jit_type_long, long_value);
+primitive!(u64, jit_type_ulong, ulong_value);
+primitive!(isize, jit_type_nint, nint_value);
+primitive!(usize, jit_type_nuint, nuint_value);
+primitive!(f32, jit_type_float32, float32_value);
+primitive!(f64, jit_type_float64, float64_value);
+
+impl<T> JitPrimitive for *mut T {
+    fn jit_type() -> JitType {
+        unsafe { JitType::new(jit_type_void_ptr) }
+    }
+}
+
+impl<T> Sealed for *mut T {
+    fn to_constant(self) -> jit_constant_t__bindgen_ty_1 {
+        jit_constant_t__bindgen_ty_1 { ptr_value: self as *mut c_void }
+    }
+}
+
+impl<T> JitPrimitive for *const T {
+    fn jit_type() -> JitType {
+        unsafe { JitType::new(jit_type_void_ptr) }
+    }
+}
+
+impl<T> Sealed for *const T {
+    fn to_constant(self) -> jit_constant_t__bindgen_ty_1 {
+        jit_constant_t__bindgen_ty_1 { ptr_value: self as *mut c_void }
+    }
+}
+
+impl<T: JitPrimitive> JitReturn for T {
+    fn jit_type() -> JitType {
+        <T as JitPrimitive>::jit_type()
+    }
+}
+
+impl JitReturn for () {
+    fn jit_type() -> JitType {
+        unsafe { JitType::new(jit_type_void) }
+    }
+}
+
+// A tuple of primitives used as the argument list of a jit'ed function.
+pub trait JitArgs {
+    fn jit_types() -> Vec<JitType>;
+
+    // Pointers to each arg, the form jit_function_apply takes them in. Only valid while self is.
+    #[doc(hidden)]
+    fn arg_ptrs(&mut self) -> Vec<*mut c_void>;
+}
+
+// An extern "C" fn pointer type whose signature can be described to libjit.
+pub trait JitFn: Copy {
+    type Args: JitArgs;
+    type Ret: JitReturn;
+
+    // Safety: ptr must point to a function with exactly this signature.
+    #[doc(hidden)]
+    unsafe fn from_ptr(ptr: *mut c_void) -> Self;
+    #[doc(hidden)]
+    fn as_ptr(self) -> *mut c_void;
+}
+
+macro_rules! args {
+    ($($arg:ident),*) => {
+        impl<$($arg: JitPrimitive),*> JitArgs for ($($arg,)*) {
+            fn jit_types() -> Vec<JitType> {
+                vec![$(<$arg as JitPrimitive>::jit_type()),*]
+            }
+
+            #[allow(non_snake_case)]
+            fn arg_ptrs(&mut self) -> Vec<*mut c_void> {
+                let ($($arg,)*) = self;
+                vec![$($arg as *mut $arg as *mut c_void),*]
+            }
+        }
+
+        impl<$($arg: JitPrimitive,)* Ret: JitReturn> JitFn for extern "C" fn($($arg),*) -> Ret {
+            type Args = ($($arg,)*);
+            type Ret = Ret;
+
+            unsafe fn from_ptr(ptr: *mut c_void) -> Self {
+                std::mem::transmute(ptr)
+            }
+            fn as_ptr(self) -> *mut c_void {
+                self as *mut c_void
+            }
+        }
+    }
+}
+
+args!();
+args!(A);
+args!(A, B);
+args!(A, B, C);
+args!(A, B, C, D);
+args!(A, B, C, D, E);
+args!(A, B, C, D, E, F);
+args!(A, B, C, D, E, F, G);
+args!(A, B, C, D, E, F, G, H);
+
@@ -1,11 +1,321 @@
-use gnu_libjit_sys::jit_type_t;
+use std::ffi::{CStr, CString};
+use std::fmt::{Display, Formatter};
+use std::os::raw::{c_char, c_int, c_uint};
+use gnu_libjit_sys::{jit_abi_t, jit_type_copy, jit_type_create_pointer, jit_type_create_signature, jit_type_create_tagged, jit_type_free, jit_type_get_abi, jit_type_get_alignment, jit_type_get_field, jit_type_get_kind, jit_type_get_offset, jit_type_get_param, jit_type_get_ref, jit_type_get_return, jit_type_get_size, jit_type_get_tagged_data, jit_type_get_tagged_kind, jit_type_get_tagged_type, jit_type_is_pointer, jit_type_is_primitive, jit_type_normalize, jit_type_num_fields, jit_type_num_params, jit_type_t, JIT_TYPETAG_NAME, JIT_TYPE_FIRST_TAGGED, JIT_TYPE_VOID, JIT_TYPE_SBYTE, JIT_TYPE_UBYTE, JIT_TYPE_SHORT, JIT_TYPE_USHORT, JIT_TYPE_INT, JIT_TYPE_UINT, JIT_TYPE_NINT, JIT_TYPE_NUINT, JIT_TYPE_LONG, JIT_TYPE_ULONG, JIT_TYPE_FLOAT32, JIT_TYPE_FLOAT64, JIT_TYPE_NFLOAT, JIT_TYPE_STRUCT, JIT_TYPE_UNION, JIT_TYPE_SIGNATURE, JIT_TYPE_PTR};
+use libc::c_void;
+use crate::{Abi, Error};
 
-#[derive(Clone, Copy, Debug)]
+// What sort of type a JitType is, see JitType::kind
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeKind {
+    Void,
+    SByte,
+    UByte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    NInt,
+    NUInt,
+    Long,
+    ULong,
+    Float32,
+    Float64,
+    NFloat,
+    Struct,
+    Union,
+    Signature,
+    Ptr,
+    // A tagged type, holds the tag kind
+    Tagged(c_int),
+    Invalid,
+}
+
+impl TypeKind {
+    fn from_raw(kind: c_int) -> TypeKind {
+        // Every kind but JIT_TYPE_INVALID is bound as unsigned
+        let kind = match u32::try_from(kind) {
+            Ok(kind) => kind,
+            Err(_) => return TypeKind::Invalid,
+        };
+        if kind >= JIT_TYPE_FIRST_TAGGED {
+            return TypeKind::Tagged((kind - JIT_TYPE_FIRST_TAGGED) as c_int);
+        }
+        match kind {
+            JIT_TYPE_VOID => TypeKind::Void,
+            JIT_TYPE_SBYTE => TypeKind::SByte,
+            JIT_TYPE_UBYTE => TypeKind::UByte,
+            JIT_TYPE_SHORT => TypeKind::Short,
+            JIT_TYPE_USHORT => TypeKind::UShort,
+            JIT_TYPE_INT => TypeKind::Int,
+            JIT_TYPE_UINT => TypeKind::UInt,
+            JIT_TYPE_NINT => TypeKind::NInt,
+            JIT_TYPE_NUINT => TypeKind::NUInt,
+            JIT_TYPE_LONG => TypeKind::Long,
+            JIT_TYPE_ULONG => TypeKind::ULong,
+            JIT_TYPE_FLOAT32 => TypeKind::Float32,
+            JIT_TYPE_FLOAT64 => TypeKind::Float64,
+            JIT_TYPE_NFLOAT => TypeKind::NFloat,
+            JIT_TYPE_STRUCT => TypeKind::Struct,
+            JIT_TYPE_UNION => TypeKind::Union,
+            JIT_TYPE_SIGNATURE => TypeKind::Signature,
+            JIT_TYPE_PTR => TypeKind::Ptr,
+            _ => TypeKind::Invalid,
+        }
+    }
+}
+
+// A reference counted libjit type. Primitive types are never freed, every other type
+// (pointers, signatures, structs, tagged types) is freed when the last JitType referencing
+// it (or value/function using it) is dropped.
+#[derive(Debug)]
 pub struct JitType {
     pub(crate) inner: jit_type_t,
 }
 impl JitType {
-    pub fn new(inner: jit_type_t) -> JitType {
+    /// Wraps a libjit type taking a new reference to it
+    /// # Safety
+    /// inner must be a valid jit_type_t, eg: one of the jit_type_* globals
+    pub unsafe fn new(inner: jit_type_t) -> JitType {
+        JitType { inner: jit_type_copy(inner) }
+    }
+
+    // Wraps a type we already hold a reference to eg: one that was just created
+    pub(crate) fn from_owned(inner: jit_type_t) -> JitType {
         JitType { inner }
     }
-}
\ No newline at end of file
+
+    // A pointer to a value of this type
+    pub fn pointer_to(&self) -> JitType {
+        JitType::from_owned(unsafe { jit_type_create_pointer(self.inner, 1) })
+    }
+
+    // The type of a function, for use with insn_call_indirect and friends
+    pub fn signature(abi: Abi, return_type: &JitType, params: &[JitType]) -> JitType {
+        JitType::from_owned(create_signature(abi, return_type, params))
+    }
+
+    // This type with a tag attached, kind is one of the JIT_TYPETAG_* constants or a custom
+    // value. Tags don't change how the type is compiled, they're for front ends to use.
+    pub fn tagged(&self, kind: c_int) -> JitType {
+        JitType::from_owned(unsafe { jit_type_create_tagged(self.inner, kind, std::ptr::null_mut(), None, 1) })
+    }
+
+    // This type tagged with JIT_TYPETAG_NAME and a name. libjit stores names as C strings so
+    // name can't contain a nul byte.
+    pub fn named(&self, name: &str) -> Result<JitType, Error> {
+        let name = CString::new(name).map_err(|_| Error::InvalidType(format!("Type name {:?} contains a nul byte", name)))?;
+        let name = name.into_raw();
+        Ok(JitType::from_owned(unsafe {
+            jit_type_create_tagged(self.inner, JIT_TYPETAG_NAME as c_int, name as *mut c_void, Some(free_name), 1)
+        }))
+    }
+
+    // The kind of the outermost tag or None if the type is not tagged
+    pub fn tagged_kind(&self) -> Option<c_int> {
+        let kind = unsafe { jit_type_get_tagged_kind(self.inner) };
+        if kind == 0 { None } else { Some(kind) }
+    }
+
+    // The name given to named
+    pub fn name(&self) -> Option<String> {
+        if self.tagged_kind() != Some(JIT_TYPETAG_NAME as c_int) {
+            return None;
+        }
+        unsafe {
+            let data = jit_type_get_tagged_data(self.inner) as *const c_char;
+            if data.is_null() {
+                return None;
+            }
+            Some(CStr::from_ptr(data).to_string_lossy().into_owned())
+        }
+    }
+
+    pub fn kind(&self) -> TypeKind {
+        TypeKind::from_raw(unsafe { jit_type_get_kind(self.inner) })
+    }
+
+    // Size in bytes, 0 for void and signatures
+    pub fn size(&self) -> usize {
+        unsafe { jit_type_get_size(self.inner) as usize }
+    }
+
+    pub fn alignment(&self) -> usize {
+        unsafe { jit_type_get_alignment(self.inner) as usize }
+    }
+
+    // Void, the integer and the float types
+    pub fn is_primitive(&self) -> bool {
+        unsafe { jit_type_is_primitive(self.inner) != 0 }
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        unsafe { jit_type_is_pointer(self.inner) != 0 }
+    }
+
+    // The type a pointer points to or None if this isn't a pointer
+    pub fn pointee(&self) -> Option<JitType> {
+        let pointee = unsafe { jit_type_get_ref(self.inner) };
+        if pointee.is_null() { None } else { Some(unsafe { JitType::new(pointee) }) }
+    }
+
+    // Number of params of a signature, 0 for every other type
+    pub fn num_params(&self) -> usize {
+        unsafe { jit_type_num_params(self.inner) as usize }
+    }
+
+    pub fn param(&self, index: usize) -> Option<JitType> {
+        let index = c_uint::try_from(index).ok()?;
+        let param = unsafe { jit_type_get_param(self.inner, index) };
+        if param.is_null() { None } else { Some(unsafe { JitType::new(param) }) }
+    }
+
+    // The return type of a signature or None if this isn't a signature
+    pub fn return_type(&self) -> Option<JitType> {
+        let ret = unsafe { jit_type_get_return(self.inner) };
+        if ret.is_null() { None } else { Some(unsafe { JitType::new(ret) }) }
+    }
+
+    // The type underneath a tag or None if this isn't tagged
+    pub fn untagged(&self) -> Option<JitType> {
+        let inner = unsafe { jit_type_get_tagged_type(self.inner) };
+        if inner.is_null() { None } else { Some(unsafe { JitType::new(inner) }) }
+    }
+
+    fn fields(&self) -> Vec<(usize, JitType)> {
+        (0..unsafe { jit_type_num_fields(self.inner) }).map(|idx| unsafe {
+            (jit_type_get_offset(self.inner, idx) as usize, JitType::new(jit_type_get_field(self.inner, idx)))
+        }).collect()
+    }
+
+    // libjit describes some types differently depending on platform (nint is int or long,
+    // pointers are nints) so compare the normalized form.
+    pub(crate) fn abi_eq(&self, other: &JitType) -> bool {
+        unsafe {
+            jit_type_get_kind(jit_type_normalize(self.inner)) == jit_type_get_kind(jit_type_normalize(other.inner))
+        }
+    }
+}
+
+impl Clone for JitType {
+    fn clone(&self) -> JitType {
+        unsafe { JitType::new(self.inner) }
+    }
+}
+
+// Types are compared structurally, libjit hands out a new jit_type_t each time a pointer,
+// signature, struct or tagged type is created. Named types are equal when their names are.
+impl PartialEq for JitType {
+    fn eq(&self, other: &JitType) -> bool {
+        if self.inner == other.inner {
+            return true;
+        }
+        let kind = self.kind();
+        if kind != other.kind() {
+            return false;
+        }
+        match kind {
+            TypeKind::Ptr => self.pointee() == other.pointee(),
+            TypeKind::Signature => {
+                let same_abi = unsafe { jit_type_get_abi(self.inner) == jit_type_get_abi(other.inner) };
+                same_abi
+                    && self.return_type() == other.return_type()
+                    && self.num_params() == other.num_params()
+                    && (0..self.num_params()).all(|i| self.param(i) == other.param(i))
+            }
+            TypeKind::Struct | TypeKind::Union => {
+                self.size() == other.size() && self.alignment() == other.alignment() && self.fields() == other.fields()
+            }
+            TypeKind::Tagged(tag) if tag == JIT_TYPETAG_NAME as c_int => {
+                self.name() == other.name() && self.untagged() == other.untagged()
+            }
+            TypeKind::Tagged(_) => {
+                let same_data = unsafe { jit_type_get_tagged_data(self.inner) == jit_type_get_tagged_data(other.inner) };
+                same_data && self.untagged() == other.untagged()
+            }
+            // Primitive types are singletons
+            _ => true,
+        }
+    }
+}
+
+// Prints the type the way C would spell it, eg: int, float64*, int(int, long)
+impl Display for JitType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind() {
+            TypeKind::Void => write!(f, "void"),
+            TypeKind::SByte => write!(f, "sbyte"),
+            TypeKind::UByte => write!(f, "ubyte"),
+            TypeKind::Short => write!(f, "short"),
+            TypeKind::UShort => write!(f, "ushort"),
+            TypeKind::Int => write!(f, "int"),
+            TypeKind::UInt => write!(f, "uint"),
+            TypeKind::NInt => write!(f, "nint"),
+            TypeKind::NUInt => write!(f, "nuint"),
+            TypeKind::Long => write!(f, "long"),
+            TypeKind::ULong => write!(f, "ulong"),
+            TypeKind::Float32 => write!(f, "float32"),
+            TypeKind::Float64 => write!(f, "float64"),
+            TypeKind::NFloat => write!(f, "nfloat"),
+            TypeKind::Ptr => match self.pointee() {
+                Some(pointee) => write!(f, "{}*", pointee),
+                None => write!(f, "void*"),
+            },
+            TypeKind::Signature => {
+                match self.return_type() {
+                    Some(ret) => write!(f, "{}(", ret)?,
+                    None => write!(f, "void(")?,
+                }
+                for i in 0..self.num_params() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    if let Some(param) = self.param(i) {
+                        write!(f, "{}", param)?;
+                    }
+                }
+                write!(f, ")")
+            }
+            TypeKind::Struct | TypeKind::Union => {
+                let keyword = if self.kind() == TypeKind::Struct { "struct" } else { "union" };
+                write!(f, "{} {{ ", keyword)?;
+                for (_, field) in self.fields() {
+                    write!(f, "{}; ", field)?;
+                }
+                write!(f, "}}")
+            }
+            TypeKind::Tagged(tag) => match self.name() {
+                Some(name) => write!(f, "{}", name),
+                None => match self.untagged() {
+                    Some(inner) => write!(f, "{}<tag {}>", inner, tag),
+                    None => write!(f, "<tag {}>", tag),
+                },
+            },
+            TypeKind::Invalid => write!(f, "<invalid>"),
+        }
+    }
+}
+
+impl Drop for JitType {
+    fn drop(&mut self) {
+        unsafe { jit_type_free(self.inner) }
+    }
+}
+
+unsafe extern "C" fn free_name(data: *mut c_void) {
+    drop(CString::from_raw(data as *mut c_char));
+}
+
+// Creates a new signature type, the caller must jit_type_free it. Null if libjit is out of memory.
+pub(crate) fn create_signature(abi: Abi, return_type: &JitType, params: &[JitType]) -> jit_type_t {
+    let mut params_libjit: Vec<jit_type_t> = params.iter().map(|p| p.inner).collect();
+    unsafe {
+        jit_type_create_signature(
+            abi as jit_abi_t,
+            return_type.inner,
+            params_libjit.as_mut_ptr(),
+            params.len() as c_uint,
+            1,
+        )
+    }
+}
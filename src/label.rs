@@ -1,4 +1,12 @@
-use gnu_libjit_sys::{jit_label_t};
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::os::raw::c_int;
+use gnu_libjit_sys::{jit_function_get_meta, jit_function_set_meta, jit_function_t, jit_label_t};
+use libc::c_void;
+use crate::Error;
+
+// Key of the LabelUses stored in the function's metadata, the catcher uses 1000
+const LABELS_META: c_int = 1001;
 
 pub struct Label {
     pub(crate) inner: jit_label_t,
@@ -18,4 +26,85 @@ impl Label {
             inner: value
         }
     }
-}
\ No newline at end of file
+
+    pub(crate) fn is_undefined(&self) -> bool {
+        self.inner == Label::new().inner
+    }
+}
+
+// The labels of a loop built by Function::while_loop or Function::loop_, pass it to
+// Function::break_ and Function::continue_. Like Value it can't leave the function it belongs to.
+/// ```compile_fail
+/// use gnu_libjit::{Abi, Context};
+/// let context = Context::new();
+/// let mut func_a = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+/// let mut func_b = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+/// func_a.build(|func_a| {
+///     func_a.loop_(|_, lp| func_b.build(|func_b| func_b.break_(&lp)));
+/// });
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Loop<'f> {
+    break_label: jit_label_t,
+    continue_label: jit_label_t,
+    _function: PhantomData<fn(&'f ()) -> &'f ()>,
+}
+impl Loop<'_> {
+    pub(crate) fn new(break_label: jit_label_t, continue_label: jit_label_t) -> Self {
+        Loop { break_label, continue_label, _function: PhantomData }
+    }
+    pub(crate) fn break_label(&self) -> Label {
+        Label { inner: self.break_label }
+    }
+    pub(crate) fn continue_label(&self) -> Label {
+        Label { inner: self.continue_label }
+    }
+}
+
+// Labels that have been branched to and labels that have been placed, compiling a function that
+// jumps to a label that was never placed is refused. Like the catcher it lives in the function's
+// metadata so that on-demand compilation can check it too.
+#[derive(Default)]
+struct LabelUses {
+    branch_targets: HashSet<jit_label_t>,
+    placed: HashSet<jit_label_t>,
+}
+
+unsafe fn label_uses(function: jit_function_t) -> *mut LabelUses {
+    let existing = jit_function_get_meta(function, LABELS_META) as *mut LabelUses;
+    if !existing.is_null() {
+        return existing;
+    }
+    let uses = Box::into_raw(Box::<LabelUses>::default());
+    jit_function_set_meta(function, LABELS_META, uses as *mut c_void, Some(free_label_uses), 1);
+    uses
+}
+
+// libjit skips branches it can prove are never taken (eg: on a constant) without
+// allocating the label, those don't need placing.
+pub(crate) unsafe fn record_branch_target(function: jit_function_t, label: &Label) {
+    if !label.is_undefined() {
+        (*label_uses(function)).branch_targets.insert(label.inner);
+    }
+}
+
+pub(crate) unsafe fn record_placed_label(function: jit_function_t, label: jit_label_t) {
+    (*label_uses(function)).placed.insert(label);
+}
+
+// Fails if a label was branched to but never placed with insn_label
+pub(crate) unsafe fn check_labels(function: jit_function_t) -> Result<(), Error> {
+    let uses = jit_function_get_meta(function, LABELS_META) as *mut LabelUses;
+    if uses.is_null() {
+        return Ok(());
+    }
+    let unplaced = (*uses).branch_targets.difference(&(*uses).placed).count();
+    if unplaced != 0 {
+        return Err(Error::UnplacedLabel(format!("{} label(s) branched to but never placed with insn_label", unplaced)));
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn free_label_uses(data: *mut c_void) {
+    drop(Box::from_raw(data as *mut LabelUses));
+}
@@ -9,10 +9,21 @@ mod value;
 mod test;
 mod util;
 mod label;
+mod jit_primitive;
+mod compiled_fn;
+mod error;
+mod struct_type;
+mod exception;
+mod jit_arg;
 
 pub use context::Context;
-pub use jit_type::JitType;
+pub use jit_type::{JitType, TypeKind};
 pub use abi::Abi;
-pub use function::{Function};
-pub use label::Label;
-pub use value::Value;
\ No newline at end of file
+pub use function::{Function, FunctionBuilder};
+pub use label::{Label, Loop};
+pub use value::Value;
+pub use jit_primitive::{JitPrimitive, JitReturn, JitArgs, JitFn};
+pub use compiled_fn::CompiledFn;
+pub use jit_arg::JitArg;
+pub use error::{Error, JitRuntimeError};
+pub use struct_type::{StructBuilder, StructType};
\ No newline at end of file
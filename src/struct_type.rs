@@ -0,0 +1,158 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_uint};
+use gnu_libjit_sys::{jit_nint, jit_type_create_struct, jit_type_create_union, jit_type_find_name, jit_type_get_alignment, jit_type_get_field, jit_type_get_offset, jit_type_get_size, jit_type_num_fields, jit_type_set_names, jit_type_set_offset, jit_type_set_size_and_alignment, jit_type_t};
+use crate::{Error, JitType};
+
+// A struct or union type with named fields. Create one with StructBuilder or jit_struct!.
+pub struct StructType {
+    jit_type: JitType,
+}
+
+impl StructType {
+    pub fn jit_type(&self) -> JitType {
+        self.jit_type.clone()
+    }
+
+    // Size in bytes including any trailing padding
+    pub fn size(&self) -> usize {
+        unsafe { jit_type_get_size(self.jit_type.inner) as usize }
+    }
+
+    pub fn alignment(&self) -> usize {
+        unsafe { jit_type_get_alignment(self.jit_type.inner) as usize }
+    }
+
+    pub fn num_fields(&self) -> usize {
+        unsafe { jit_type_num_fields(self.jit_type.inner) as usize }
+    }
+
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        let name = CString::new(name).ok()?;
+        let idx = unsafe { jit_type_find_name(self.jit_type.inner, name.as_ptr()) };
+        // JIT_INVALID_NAME
+        if idx == c_uint::MAX { None } else { Some(idx as usize) }
+    }
+
+    // Offset in bytes of the named field from the start of the struct
+    pub fn offset(&self, name: &str) -> Option<usize> {
+        let idx = self.field_index(name)?;
+        Some(unsafe { jit_type_get_offset(self.jit_type.inner, idx as c_uint) as usize })
+    }
+
+    pub fn field_type(&self, name: &str) -> Option<JitType> {
+        let idx = self.field_index(name)?;
+        Some(unsafe { JitType::new(jit_type_get_field(self.jit_type.inner, idx as c_uint)) })
+    }
+
+    // The offset and type of a field or UnknownField
+    pub(crate) fn field(&self, name: &str) -> Result<(usize, JitType), Error> {
+        match (self.offset(name), self.field_type(name)) {
+            (Some(offset), Some(field_type)) => Ok((offset, field_type)),
+            _ => Err(Error::UnknownField(name.to_string())),
+        }
+    }
+}
+
+// Builds a StructType. Fields are laid out like a C compiler would unless given explicit
+// offsets with field_at.
+// eg: let point = StructBuilder::new().field("x", Context::float64_type()).field("y", Context::float64_type()).build()?;
+#[derive(Default)]
+pub struct StructBuilder {
+    fields: Vec<(String, JitType, Option<usize>)>,
+    size_and_alignment: Option<(usize, usize)>,
+}
+
+impl StructBuilder {
+    pub fn new() -> StructBuilder {
+        StructBuilder::default()
+    }
+
+    pub fn field(mut self, name: &str, field_type: JitType) -> StructBuilder {
+        self.fields.push((name.to_string(), field_type, None));
+        self
+    }
+
+    pub fn field_at(mut self, name: &str, field_type: JitType, offset: usize) -> StructBuilder {
+        self.fields.push((name.to_string(), field_type, Some(offset)));
+        self
+    }
+
+    // Override the computed size and alignment eg: to match a rust type with extra alignment
+    pub fn size_and_alignment(mut self, size: usize, alignment: usize) -> StructBuilder {
+        self.size_and_alignment = Some((size, alignment));
+        self
+    }
+
+    pub fn build(self) -> Result<StructType, Error> {
+        self.create(false)
+    }
+
+    // Build a union, every field is at offset 0
+    pub fn build_union(self) -> Result<StructType, Error> {
+        self.create(true)
+    }
+
+    fn create(self, union: bool) -> Result<StructType, Error> {
+        let mut names = vec![];
+        for (idx, (name, _, _)) in self.fields.iter().enumerate() {
+            if self.fields[..idx].iter().any(|(other, _, _)| other == name) {
+                return Err(Error::InvalidType(format!("Duplicate field {}", name)));
+            }
+            names.push(CString::new(name.as_str()).map_err(|_| Error::InvalidType(format!("Field name {:?} contains a nul byte", name)))?);
+        }
+        let mut field_types: Vec<jit_type_t> = self.fields.iter().map(|(_, field_type, _)| field_type.inner).collect();
+        let mut name_ptrs: Vec<*mut c_char> = names.iter().map(|name| name.as_ptr() as *mut c_char).collect();
+        unsafe {
+            let inner = if union {
+                jit_type_create_union(field_types.as_mut_ptr(), field_types.len() as c_uint, 1)
+            } else {
+                jit_type_create_struct(field_types.as_mut_ptr(), field_types.len() as c_uint, 1)
+            };
+            if inner.is_null() {
+                return Err(Error::InvalidType("libjit could not create the type".to_string()));
+            }
+            // Owned from here on so it's freed on error
+            let struct_type = StructType { jit_type: JitType::from_owned(inner) };
+            // libjit copies the names
+            if jit_type_set_names(inner, name_ptrs.as_mut_ptr(), name_ptrs.len() as c_uint) == 0 {
+                return Err(Error::InvalidType("libjit could not set the field names".to_string()));
+            }
+            for (idx, (_, _, offset)) in self.fields.iter().enumerate() {
+                if let Some(offset) = offset {
+                    jit_type_set_offset(inner, idx as c_uint, *offset as _);
+                }
+            }
+            if let Some((size, alignment)) = self.size_and_alignment {
+                jit_type_set_size_and_alignment(inner, size as jit_nint, alignment as jit_nint);
+            }
+            Ok(struct_type)
+        }
+    }
+}
+
+// Describe a #[repr(C)] rust struct to libjit using its real field offsets, size and alignment.
+// Every field must be a JitPrimitive.
+// eg:
+// #[repr(C)]
+// struct Point { x: f64, y: i32 }
+// let point_type = jit_struct!(Point { x: f64, y: i32 })?;
+/// ```compile_fail
+/// #[repr(C)]
+/// struct Point { x: f64, y: i32 }
+/// let point_type = gnu_libjit::jit_struct!(Point { x: f64, y: i64 });
+/// ```
+#[macro_export]
+macro_rules! jit_struct {
+    ($struct_name:ident { $($field:ident: $field_ty:ty),* $(,)? }) => {{
+        // Fails to compile if a listed type doesn't match the struct's field
+        $(let _ = |s: &$struct_name| { let _: &$field_ty = &s.$field; };)*
+        $crate::StructBuilder::new()
+            $(.field_at(
+                stringify!($field),
+                <$field_ty as $crate::JitPrimitive>::jit_type(),
+                ::std::mem::offset_of!($struct_name, $field),
+            ))*
+            .size_and_alignment(::std::mem::size_of::<$struct_name>(), ::std::mem::align_of::<$struct_name>())
+            .build()
+    }}
+}
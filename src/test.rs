@@ -1,44 +1,49 @@
 #[cfg(test)]
 use std::fmt::Debug;
 #[cfg(test)]
-use gnu_libjit_sys::{jit_type_int, jit_type_float64, jit_type_ubyte};
+use gnu_libjit_sys::{jit_type_int, jit_type_float64, jit_type_ubyte, jit_type_void_ptr, JIT_TYPETAG_NAME, JIT_RESULT_NULL_FUNCTION, jit_exception_builtin};
 #[cfg(test)]
-use crate::{Abi, Context, Function, JitType, Label};
+use crate::{Abi, Context, Error, Function, FunctionBuilder, JitArg, JitPrimitive, JitRuntimeError, JitType, Label, Loop, TypeKind, Value};
 
 #[cfg(test)]
-type TestT = Box<dyn Fn(&mut Function, &mut Context)>;
+type TestT = Box<dyn Fn(&mut FunctionBuilder, &Context)>;
 
 #[cfg(test)]
 macro_rules! jit_int {
-    () => { JitType::new(unsafe { jit_type_int } ) }
+    () => { unsafe { JitType::new(jit_type_int) } }
 }
 
 #[cfg(test)]
 macro_rules! jit_ubyte {
-    () => { JitType::new(unsafe { jit_type_ubyte } ) }
+    () => { unsafe { JitType::new(jit_type_ubyte) } }
+}
+
+#[cfg(test)]
+macro_rules! jit_void_ptr {
+    () => { unsafe { JitType::new(jit_type_void_ptr) } }
 }
 
 #[cfg(test)]
 macro_rules! jit_double {
-    () => { JitType::new(unsafe { jit_type_float64 } ) }
+    () => { unsafe { JitType::new(jit_type_float64) } }
 }
 
 #[cfg(test)]
-fn make_test<RetT>(test: TestT, expected: RetT, jit_type: JitType) where RetT: Debug + Default + PartialEq {
-    let mut context = Context::new();
+fn make_test<RetT>(test: TestT, expected: RetT, jit_type: JitType) where RetT: Debug + PartialEq + JitPrimitive {
+    let context = Context::new();
     context.build_start();
     let mut func = context.function(Abi::Cdecl, jit_type, vec![]).unwrap();
-    test(&mut func, &mut context);
+    func.build(|func| test(func, &context));
     println!("{}", func.dump().unwrap());
-    func.compile();
+    func.compile().unwrap();
     context.build_end();
-    assert_eq!(func.to_closure::<fn() -> RetT>()(), expected);
+    assert_eq!(func.to_closure::<extern "C" fn() -> RetT>().unwrap()(), expected);
 }
 
 #[test]
 fn test_const() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let zero = func.create_long_constant(0);
         func.insn_return(&zero);
     };
@@ -47,8 +52,8 @@ fn test_const() {
 
 #[test]
 fn test_and_falsy() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let zero = func.create_int_constant(0);
         let one = func.create_int_constant(1);
         let res = func.insn_and(&zero, &one);
@@ -59,8 +64,8 @@ fn test_and_falsy() {
 
 #[test]
 fn test_and_truthy() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let one = func.create_int_constant(1);
         let res = func.insn_and(&one, &one);
         func.insn_return(&res);
@@ -70,8 +75,8 @@ fn test_and_truthy() {
 
 #[test]
 fn test_and_bitwise() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_int_constant(13);
         let b = func.create_int_constant(4);
         let res = func.insn_and(&a, &b);
@@ -82,8 +87,8 @@ fn test_and_bitwise() {
 
 #[test]
 fn test_or() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let zero = func.create_int_constant(0);
         let one = func.create_int_constant(1);
         let res = func.insn_or(&zero, &one);
@@ -94,8 +99,8 @@ fn test_or() {
 
 #[test]
 fn test_xor() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let zero = func.create_int_constant(0);
         let one = func.create_int_constant(1);
         let res = func.insn_or(&zero, &one);
@@ -106,8 +111,8 @@ fn test_xor() {
 
 #[test]
 fn test_xor_equal() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_int_constant(1);
         let b = func.create_int_constant(1);
         let res = func.insn_xor(&a, &b);
@@ -118,8 +123,8 @@ fn test_xor_equal() {
 
 #[test]
 fn test_or_bitwise() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_int_constant(1);
         let b = func.create_int_constant(2);
         let res = func.insn_or(&a, &b);
@@ -130,8 +135,8 @@ fn test_or_bitwise() {
 
 #[test]
 fn test_not() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_ubyte_constant(0);
         let res = func.insn_not(&a);
         func.insn_return(&res);
@@ -141,8 +146,8 @@ fn test_not() {
 
 #[test]
 fn test_not_striped() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_ubyte_constant(0b01010101);
         let res = func.insn_not(&a);
         func.insn_return(&res);
@@ -154,8 +159,8 @@ fn test_not_striped() {
 
 #[test]
 fn test_add_int() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let three = func.create_long_constant(3);
         let one = func.create_long_constant(1);
         let result = func.insn_add(&three, &one);
@@ -166,8 +171,8 @@ fn test_add_int() {
 
 #[test]
 fn test_sub_int() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let three = func.create_long_constant(3);
         let one = func.create_long_constant(1);
         let result = func.insn_sub(&one, &three);
@@ -178,8 +183,8 @@ fn test_sub_int() {
 
 #[test]
 fn test_mult_int() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_long_constant(3);
         let b = func.create_long_constant(100);
         let result = func.insn_mult(&a, &b);
@@ -190,8 +195,8 @@ fn test_mult_int() {
 
 #[test]
 fn test_div_int() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_long_constant(300);
         let b = func.create_long_constant(100);
         let result = func.insn_div(&a, &b);
@@ -203,8 +208,8 @@ fn test_div_int() {
 
 #[test]
 fn test_add_double() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(1.0);
         let b = func.create_float64_constant(1.0);
         let result = func.insn_add(&a, &b);
@@ -215,8 +220,8 @@ fn test_add_double() {
 
 #[test]
 fn test_sub_double() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let three = func.create_float64_constant(3.0);
         let one = func.create_float64_constant(1.0);
         let result = func.insn_sub(&one, &three);
@@ -227,8 +232,8 @@ fn test_sub_double() {
 
 #[test]
 fn test_mult_double() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(3.0);
         let b = func.create_float64_constant(100.0);
         let result = func.insn_mult(&a, &b);
@@ -239,8 +244,8 @@ fn test_mult_double() {
 
 #[test]
 fn test_div_double() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(300.0);
         let b = func.create_float64_constant(100.0);
         let result = func.insn_div(&a, &b);
@@ -251,8 +256,8 @@ fn test_div_double() {
 
 #[test]
 fn test_le() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(300.0);
         let b = func.create_float64_constant(100.0);
         let result = func.insn_lt(&a, &b);
@@ -263,8 +268,8 @@ fn test_le() {
 
 #[test]
 fn test_le_2() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(300.0);
         let b = func.create_float64_constant(300.0);
         let result = func.insn_le(&b, &a);
@@ -275,8 +280,8 @@ fn test_le_2() {
 
 #[test]
 fn test_le_3() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(300.0);
         let b = func.create_float64_constant(100.0);
         let result = func.insn_le(&b, &a);
@@ -287,8 +292,8 @@ fn test_le_3() {
 
 #[test]
 fn test_lt() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(300.0);
         let b = func.create_float64_constant(100.0);
         let result = func.insn_lt(&a, &b);
@@ -299,8 +304,8 @@ fn test_lt() {
 
 #[test]
 fn test_lt_2() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(300.0);
         let b = func.create_float64_constant(300.0);
         let result = func.insn_lt(&b, &a);
@@ -311,8 +316,8 @@ fn test_lt_2() {
 
 #[test]
 fn test_lt_3() {
-    use crate::{Function, Context};
-    let test = |func: &mut Function, _context: &mut Context| {
+    use crate::{FunctionBuilder, Context};
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
         let a = func.create_float64_constant(300.0);
         let b = func.create_float64_constant(100.0);
         let result = func.insn_lt(&b, &a);
@@ -323,27 +328,29 @@ fn test_lt_3() {
 
 #[test]
 fn test_branching() {
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
     let float_type = Context::float64_type();
-    let mut func = context.function(Abi::Cdecl, float_type, vec![float_type]).unwrap();
-
-    // Return 1 if arg0 == 4
-    // else return 0
-    let is_four_result = func.create_float64_constant(1.0);
-    let not_four_result = func.create_float64_constant(0.0);
-
-    let x = func.arg(0).unwrap();
-    let four = func.create_float64_constant(4.0);
-    let mut label = Label::new();
-    let eq_to_4 = func.insn_eq(&x, &four);
-    func.insn_branch_if(&eq_to_4, &mut label);
-    func.insn_return(&not_four_result);
-    func.insn_label(&mut label);
-    func.insn_return(&is_four_result);
-    func.compile();
-    context.build_end();
-    let result: extern "C" fn(f64) -> f64 = func.to_closure();
+    let mut func = context.function(Abi::Cdecl, float_type.clone(), vec![float_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // Return 1 if arg0 == 4
+        // else return 0
+        let is_four_result = func.create_float64_constant(1.0);
+        let not_four_result = func.create_float64_constant(0.0);
+
+        let x = func.arg(0).unwrap();
+        let four = func.create_float64_constant(4.0);
+        let mut label = Label::new();
+        let eq_to_4 = func.insn_eq(&x, &four);
+        func.insn_branch_if(&eq_to_4, &mut label);
+        func.insn_return(&not_four_result);
+        func.insn_label(&mut label);
+        func.insn_return(&is_four_result);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    let result: extern "C" fn(f64) -> f64 = func.to_closure().unwrap();
     assert_eq!(result(4.0), 1.0);
     assert_eq!(result(4.1), 0.0);
     assert_eq!(result(-10004.1), 0.0);
@@ -351,46 +358,119 @@ fn test_branching() {
 
 #[test]
 fn test_branching_on_u8() {
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
     let ubyte_type = Context::ubyte_type();
-    let mut func = context.function(Abi::Cdecl, ubyte_type, vec![ubyte_type]).unwrap();
-
-    // Return 10 if arg == 0
-    // Return 20 if arg == 1
-    // Return 30 if arg == 2
-    // By branching not doing math
-    let zero = func.create_ubyte_constant(0);
-    let one = func.create_ubyte_constant(1);
-    let n_10 = func.create_ubyte_constant(10);
-    let n_20 = func.create_ubyte_constant(20);
-    let n_30 = func.create_ubyte_constant(30);
-
-    let arg1 = func.arg(0).unwrap();
-    let is_zero = func.insn_eq(&zero, &arg1);
-    let mut not_zero_lbl = Label::new();
-    func.insn_branch_if_not(&is_zero, &mut not_zero_lbl);
-    func.insn_return(&n_10);
-
-    func.insn_label(&mut not_zero_lbl);
-    let is_one = func.insn_eq(&one, &arg1);
-    let mut not_one_lbl = Label::new();
-    func.insn_branch_if_not(&is_one, &mut not_one_lbl);
-    func.insn_return(&n_20);
-
-    func.insn_label(&mut not_one_lbl);
-    func.insn_return(&n_30);
-
-    func.compile();
+    let mut func = context.function(Abi::Cdecl, ubyte_type.clone(), vec![ubyte_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // Return 10 if arg == 0
+        // Return 20 if arg == 1
+        // Return 30 if arg == 2
+        // By branching not doing math
+        let zero = func.create_ubyte_constant(0);
+        let one = func.create_ubyte_constant(1);
+        let n_10 = func.create_ubyte_constant(10);
+        let n_20 = func.create_ubyte_constant(20);
+        let n_30 = func.create_ubyte_constant(30);
+
+        let arg1 = func.arg(0).unwrap();
+        let is_zero = func.insn_eq(&zero, &arg1);
+        let mut not_zero_lbl = Label::new();
+        func.insn_branch_if_not(&is_zero, &mut not_zero_lbl);
+        func.insn_return(&n_10);
+
+        func.insn_label(&mut not_zero_lbl);
+        let is_one = func.insn_eq(&one, &arg1);
+        let mut not_one_lbl = Label::new();
+        func.insn_branch_if_not(&is_one, &mut not_one_lbl);
+        func.insn_return(&n_20);
+
+        func.insn_label(&mut not_one_lbl);
+        func.insn_return(&n_30);
+    });
+
+    func.compile().unwrap();
     // println!("{}",func.dump().unwrap());
     // context.build_end();
 
-    let result: extern "C" fn(i8) -> i8 = func.to_closure();
+    let result: extern "C" fn(u8) -> u8 = func.to_closure().unwrap();
     assert_eq!(result(0), 10);
     assert_eq!(result(1), 20);
     assert_eq!(result(2), 30);
 }
 
+#[test]
+fn test_jump_table() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // switch x { 0..=19 => return x * 10, _ => return -1 }
+        let x = func.arg(0).unwrap();
+        let mut arms: Vec<Label> = (0..20).map(|_| Label::new()).collect();
+        let mut default = Label::new();
+        func.insn_jump_table(&x, &mut arms, &mut default);
+        for (i, arm) in arms.iter_mut().enumerate() {
+            func.insn_label(arm);
+            let ret = func.create_int_constant(i as i32 * 10);
+            func.insn_return(&ret);
+        }
+        func.insn_label(&mut default);
+        let minus_one = func.create_int_constant(-1);
+        func.insn_return(&minus_one);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(0), 0);
+    assert_eq!(result(7), 70);
+    assert_eq!(result(19), 190);
+    assert_eq!(result(20), -1);
+    assert_eq!(result(-3), -1);
+}
+
+#[test]
+fn test_jump_table_to_placed_labels() {
+    let context = Context::new();
+    context.build_start();
+    let ubyte_type = Context::ubyte_type();
+    let mut func = context.function(Abi::Cdecl, ubyte_type.clone(), vec![ubyte_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // Arms placed before the table and shared between entries
+        let x = func.arg(0).unwrap();
+        let mut start = Label::new();
+        let mut even = Label::new();
+        let mut odd = Label::new();
+        let mut default = Label::new();
+        func.insn_branch(&mut start);
+        func.insn_label(&mut even);
+        let n_2 = func.create_ubyte_constant(2);
+        func.insn_return(&n_2);
+        func.insn_label(&mut odd);
+        let n_1 = func.create_ubyte_constant(1);
+        func.insn_return(&n_1);
+        func.insn_label(&mut start);
+        let mut arms = [Label { inner: even.inner }, Label { inner: odd.inner }, Label { inner: even.inner }];
+        func.insn_jump_table(&x, &mut arms, &mut default);
+        func.insn_label(&mut default);
+        let zero = func.create_ubyte_constant(0);
+        func.insn_return(&zero);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(u8) -> u8 = func.to_closure().unwrap();
+    assert_eq!(result(0), 2);
+    assert_eq!(result(1), 1);
+    assert_eq!(result(2), 2);
+    assert_eq!(result(3), 0);
+}
+
 #[cfg(test)]
 extern "C" fn add_one_to_value(value: *mut i8)  {
     unsafe {
@@ -403,22 +483,70 @@ fn test_native_func_passing_a_ptr_over_ffi() {
     let mut value: i8 = 10;
     assert_eq!(value, 10);
     let ptr_to_value = (&mut value as *mut i8) as *mut libc::c_void;
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
     let ubyte_type = Context::ubyte_type();
-    let mut func = context.function(Abi::Cdecl, ubyte_type, vec![ubyte_type]).unwrap();
-    let ptr_constant = func.create_void_ptr_constant(ptr_to_value);
-    let zero = func.create_ubyte_constant(0);
-    func.insn_call_native(add_one_to_value as *mut libc::c_void, vec![ptr_constant], None);
-    func.insn_return(&zero);
-    func.compile();
-    let result: extern "C" fn(i8) -> i8 = func.to_closure();
+    let mut func = context.function(Abi::Cdecl, ubyte_type.clone(), vec![ubyte_type.clone()]).unwrap();
+    func.build(|func| {
+        let ptr_constant = func.create_void_ptr_constant(ptr_to_value);
+        let zero = func.create_ubyte_constant(0);
+        func.insn_call_native(add_one_to_value as *mut libc::c_void, vec![ptr_constant], None);
+        func.insn_return(&zero);
+    });
+    func.compile().unwrap();
+    let result: extern "C" fn(u8) -> u8 = func.to_closure().unwrap();
     result(0);
     assert_eq!(value, 11);
     result(0);
     assert_eq!(value, 12);
 }
 
+#[test]
+fn test_native_func_mutates_local_through_address() {
+    let context = Context::new();
+    context.build_start();
+    let sbyte_type = Context::sbyte_type();
+    let mut func = context.function(Abi::Cdecl, sbyte_type.clone(), vec![sbyte_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let local = func.create_local(sbyte_type.clone());
+        local.set_addressable();
+        assert!(local.is_addressable());
+        func.insn_store(&local, &x);
+        let ptr = func.insn_address_of(&local);
+        func.insn_call_native(add_one_to_value as *mut libc::c_void, vec![ptr.clone()], None);
+        func.insn_call_native(add_one_to_value as *mut libc::c_void, vec![ptr], None);
+        func.insn_return(&local);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i8) -> i8 = func.to_closure().unwrap();
+    assert_eq!(result(10), 12);
+    assert_eq!(result(-5), -3);
+}
+
+#[test]
+fn test_address_of_param() {
+    let context = Context::new();
+    context.build_start();
+    let sbyte_type = Context::sbyte_type();
+    let mut func = context.function(Abi::Cdecl, sbyte_type.clone(), vec![sbyte_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let ptr = func.insn_address_of(&x);
+        assert!(x.is_addressable());
+        assert!(ptr.value_type().is_pointer());
+        func.insn_call_native(add_one_to_value as *mut libc::c_void, vec![ptr], None);
+        func.insn_return(&x);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i8) -> i8 = func.to_closure().unwrap();
+    assert_eq!(result(41), 42);
+}
+
 
 #[cfg(test)]
 fn ret_f64() -> f64 {
@@ -427,72 +555,1791 @@ fn ret_f64() -> f64 {
 
 #[test]
 fn test_native_with_ret_type() {
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
-    let mut func = context.function(Abi::Cdecl, Context::float64_type(), vec![Context::float64_type()]).unwrap();
-    let ret = func.insn_call_native(ret_f64 as *mut libc::c_void, vec![], Some(Context::float64_type()));
-    func.insn_return(&ret);
-    func.compile();
+    let mut func = context.function(Abi::Cdecl, Context::float64_type(), vec![]).unwrap();
+    func.build(|func| {
+        let ret = func.insn_call_native(ret_f64 as *mut libc::c_void, vec![], Some(Context::float64_type()));
+        func.insn_return(&ret);
+    });
+    func.compile().unwrap();
     context.build_end();
-    let result: extern "C" fn() -> f64 = func.to_closure();
+    let result: extern "C" fn() -> f64 = func.to_closure().unwrap();
     assert_eq!(result(), 123.123);
 }
 
 
 #[test]
 fn fn_test_load_and_store() {
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
 
 
     let float_type = Context::float64_type();
-    let params = vec![float_type];
-    let mut func = context.function(Abi::Cdecl, float_type, params).unwrap();
+    let params = vec![float_type.clone()];
+    let mut func = context.function(Abi::Cdecl, float_type.clone(), params).unwrap();
 
-    let x = func.arg(0).unwrap();
-    let float_ptr_1 = func.alloca(8);
-    let float_ptr_2 = func.alloca(8);
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let float_ptr_1 = func.alloca_of(Context::float64_type(), 1).unwrap();
+        let float_ptr_2 = func.alloca_of(Context::float64_type(), 1).unwrap();
 
-    let const_dbl = func.create_float64_constant(123.0);
-    func.insn_store(&float_ptr_2, &const_dbl);
+        let const_dbl = func.create_float64_constant(123.0);
+        func.insn_store_relative(&float_ptr_2, 0, &const_dbl);
 
-    func.insn_store(&float_ptr_1, &x);
-    let f1 = func.insn_load(&float_ptr_1);
+        func.insn_store_relative(&float_ptr_1, 0, &x);
+        let f1 = func.insn_load_relative(&float_ptr_1, 0, float_type.clone());
 
-    func.insn_store(&float_ptr_1, &f1);
-    let f2 = func.insn_load(&float_ptr_1);
+        func.insn_store_relative(&float_ptr_1, 0, &f1);
+        let f2 = func.insn_load_relative(&float_ptr_1, 0, float_type.clone());
 
-    func.insn_store(&float_ptr_1, &f2);
-    let f3 = func.insn_load(&float_ptr_1);
+        func.insn_store_relative(&float_ptr_1, 0, &f2);
+        let f3 = func.insn_load_relative(&float_ptr_1, 0, float_type.clone());
 
-    let const_dbl2 = func.insn_load(&float_ptr_2);
+        let const_dbl2 = func.insn_load_relative(&float_ptr_2, 0, float_type.clone());
 
-    let x_plus_123 = func.insn_add(&const_dbl2, &f3);
+        let x_plus_123 = func.insn_add(&const_dbl2, &f3);
 
-    func.insn_return(&x_plus_123);
-    func.compile();
+        func.insn_return(&x_plus_123);
+    });
+    func.compile().unwrap();
     context.build_end();
 
-    let result: extern "C" fn(f64) -> f64 = func.to_closure();
+    let result: extern "C" fn(f64) -> f64 = func.to_closure().unwrap();
     assert_eq!(result(1.0), 124.0);
 }
 
+#[test]
+fn test_store_relative_writes_rust_memory() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![jit_void_ptr!(), int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // Write arg1 to ptr[1] and return ptr[0]
+        let ptr = func.arg(0).unwrap();
+        let x = func.arg(1).unwrap();
+        func.insn_store_relative(&ptr, 4, &x);
+        let first = func.insn_load_relative(&ptr, 0, int_type.clone());
+        func.insn_return(&first);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let mut buffer: [i32; 2] = [7, 0];
+    let result: extern "C" fn(*mut i32, i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(buffer.as_mut_ptr(), 42), 7);
+    assert_eq!(buffer, [7, 42]);
+}
+
+#[test]
+fn test_load_and_store_elem() {
+    let context = Context::new();
+    context.build_start();
+    let float_type = Context::float64_type();
+    let mut func = context.function(Abi::Cdecl, float_type.clone(), vec![jit_void_ptr!()]).unwrap();
+
+    func.build(|func| {
+        // ptr[2] = ptr[0] + ptr[1]; return ptr[2]
+        let ptr = func.arg(0).unwrap();
+        let zero = func.create_int_constant(0);
+        let one = func.create_int_constant(1);
+        let two = func.create_int_constant(2);
+        let a = func.insn_load_elem(&ptr, &zero, float_type.clone());
+        let b = func.insn_load_elem(&ptr, &one, float_type.clone());
+        let sum = func.insn_add(&a, &b);
+        func.insn_store_elem(&ptr, &two, &sum);
+        let res = func.insn_load_elem(&ptr, &two, float_type.clone());
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let mut buffer: [f64; 3] = [1.5, 2.0, 0.0];
+    let result: extern "C" fn(*mut f64) -> f64 = func.to_closure().unwrap();
+    assert_eq!(result(buffer.as_mut_ptr()), 3.5);
+    assert_eq!(buffer, [1.5, 2.0, 3.5]);
+}
+
 #[test]
 fn test_unconditional_branch() {
-    let mut context = Context::new();
+    let context = Context::new();
     context.build_start();
     let int_type = Context::int_type();
     let params = vec![];
-    let mut func = context.function(Abi::Cdecl, int_type, params).unwrap();
-    let mut lbl = Label::new();
-    func.insn_branch(&mut lbl);
-    let ten = func.create_int_constant(10);
-    func.insn_return(&ten);
-    func.insn_label(&mut lbl);
-    let twenty = func.create_int_constant(20);
-    func.insn_return(&twenty);
-    func.compile();
-    context.build_end();
-    let result: extern "C" fn() -> i32 = func.to_closure();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), params).unwrap();
+    func.build(|func| {
+        let mut lbl = Label::new();
+        func.insn_branch(&mut lbl);
+        let ten = func.create_int_constant(10);
+        func.insn_return(&ten);
+        func.insn_label(&mut lbl);
+        let twenty = func.create_int_constant(20);
+        func.insn_return(&twenty);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    let result: extern "C" fn() -> i32 = func.to_closure().unwrap();
     assert_eq!(result(), 20);
-}
\ No newline at end of file
+}
+#[test]
+fn test_many_contexts_are_dropped() {
+    for _ in 0..1000 {
+        let context = Context::new();
+        context.build_start();
+        let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+        func.build(|func| {
+            let one = func.create_int_constant(1);
+            func.insn_return(&one);
+        });
+        func.compile().unwrap();
+        context.build_end();
+        let result: extern "C" fn() -> i32 = func.to_closure().unwrap();
+        assert_eq!(result(), 1);
+    }
+}
+
+#[test]
+fn test_to_closure_signature_mismatch() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        func.insn_return(&x);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    assert!(func.to_closure::<extern "C" fn(f64) -> i32>().is_err());
+    assert!(func.to_closure::<extern "C" fn(i32, i32) -> i32>().is_err());
+    assert!(func.to_closure::<extern "C" fn(i32) -> f64>().is_err());
+    assert!(func.to_closure::<extern "C" fn(i32) -> i32>().is_ok());
+}
+
+#[test]
+fn test_compiled_fn() {
+    let context = Context::new();
+    context.build_start();
+    let long_type = Context::long_type();
+    let float_type = Context::float64_type();
+    let mut func = context.function(Abi::Cdecl, float_type.clone(), vec![long_type.clone(), float_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let y = func.arg(1).unwrap();
+        let res = func.insn_mult(&x, &y);
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    assert!(func.to_compiled_fn::<(i64,), f64>().is_err());
+    let mult = func.to_compiled_fn::<(i64, f64), f64>().unwrap();
+    assert_eq!(mult.call((3, 1.5)), 4.5);
+}
+
+#[test]
+fn test_compile_empty_function_fails() {
+    let context = Context::new();
+    context.build_start();
+    let func = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+    // Nothing was built so libjit has no IR to compile
+    assert!(matches!(func.compile(), Err(Error::CompileFailed(code)) if code == JIT_RESULT_NULL_FUNCTION));
+    context.build_end();
+}
+
+#[test]
+fn test_arg_index_out_of_range() {
+    let context = Context::new();
+    let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![Context::int_type()]).unwrap();
+    func.build(|func| {
+        assert!(func.arg(0).is_ok());
+        assert!(matches!(func.arg(1), Err(Error::ArgIndexOutOfRange(_))));
+        assert!(matches!(func.arg(-1), Err(Error::ArgIndexOutOfRange(_))));
+    });
+}
+
+#[test]
+fn test_void_param_is_invalid() {
+    let context = Context::new();
+    let void_type = unsafe { JitType::new(gnu_libjit_sys::jit_type_void) };
+    let func = context.function(Abi::Cdecl, Context::int_type(), vec![void_type.clone()]);
+    assert!(matches!(func, Err(Error::InvalidSignature(_))));
+}
+
+#[test]
+fn test_rem() {
+    let rem: BinaryOp = |f, a, b| f.insn_rem(a, b);
+    assert_eq!(run_binary::<i32, i32, i32>(rem, -7, 3), -1);
+    assert_eq!(run_binary::<i64, i64, i64>(rem, 10_000_000_007, 10), 7);
+    assert_eq!(run_binary::<f64, f64, f64>(rem, 7.5, 2.0), 1.5);
+}
+
+#[test]
+fn test_rem_ieee() {
+    let rem_ieee: BinaryOp = |f, a, b| f.insn_rem_ieee(a, b);
+    assert_eq!(run_binary::<i32, i32, i32>(rem_ieee, 7, 2), 1);
+    assert_eq!(run_binary::<i64, i64, i64>(rem_ieee, 7, 2), 1);
+    // 7 / 2 = 3.5 rounds to the even 4 so the remainder is negative
+    assert_eq!(run_binary::<f64, f64, f64>(rem_ieee, 7.0, 2.0), -1.0);
+}
+
+#[test]
+fn test_neg() {
+    let neg: UnaryOp = |f, v| f.insn_neg(v);
+    assert_eq!(run_unary::<i32, i32>(neg, 5), -5);
+    assert_eq!(run_unary::<i64, i64>(neg, -5_000_000_000), 5_000_000_000);
+    assert_eq!(run_unary::<f64, f64>(neg, 2.5), -2.5);
+}
+
+#[test]
+fn test_shl() {
+    let shl: BinaryOp = |f, a, b| f.insn_shl(a, b);
+    assert_eq!(run_binary::<i32, i32, i32>(shl, 3, 4), 48);
+    assert_eq!(run_binary::<i64, i32, i64>(shl, 1, 40), 1 << 40);
+}
+
+#[test]
+fn test_shr() {
+    let shr: BinaryOp = |f, a, b| f.insn_shr(a, b);
+    assert_eq!(run_binary::<i32, i32, i32>(shr, -16, 2), -4);
+    assert_eq!(run_binary::<i64, i32, i64>(shr, -16, 2), -4);
+}
+
+#[test]
+fn test_ushr() {
+    let ushr: BinaryOp = |f, a, b| f.insn_ushr(a, b);
+    assert_eq!(run_binary::<i32, i32, i32>(ushr, -1, 28), 15);
+    assert_eq!(run_binary::<i64, i32, i64>(ushr, -1, 60), 15);
+}
+
+#[test]
+fn test_sshr() {
+    let sshr: BinaryOp = |f, a, b| f.insn_sshr(a, b);
+    assert_eq!(run_binary::<u32, i32, i32>(sshr, 0x80000000, 28), -8);
+    assert_eq!(run_binary::<i64, i32, i64>(sshr, -256, 4), -16);
+}
+
+#[test]
+fn test_min_max() {
+    let min: BinaryOp = |f, a, b| f.insn_min(a, b);
+    let max: BinaryOp = |f, a, b| f.insn_max(a, b);
+    assert_eq!(run_binary::<i32, i32, i32>(min, -3, 2), -3);
+    assert_eq!(run_binary::<i64, i64, i64>(max, -3, 5_000_000_000), 5_000_000_000);
+    assert_eq!(run_binary::<f64, f64, f64>(min, 1.5, -2.5), -2.5);
+    assert_eq!(run_binary::<f64, f64, f64>(max, 1.5, -2.5), 1.5);
+}
+
+#[test]
+fn test_abs() {
+    let abs: UnaryOp = |f, v| f.insn_abs(v);
+    assert_eq!(run_unary::<i32, i32>(abs, -9), 9);
+    assert_eq!(run_unary::<i64, i64>(abs, -9_000_000_000), 9_000_000_000);
+    assert_eq!(run_unary::<f64, f64>(abs, -0.25), 0.25);
+}
+
+#[test]
+fn test_sign() {
+    let sign: UnaryOp = |f, v| f.insn_sign(v);
+    assert_eq!(run_unary::<i32, i32>(sign, -9), -1);
+    assert_eq!(run_unary::<i64, i32>(sign, 9_000_000_000), 1);
+    assert_eq!(run_unary::<f64, i32>(sign, 0.0), 0);
+}
+
+#[cfg(test)]
+type UnaryOp = for<'ctx, 'f> fn(&mut FunctionBuilder<'ctx, 'f>, &Value<'f>) -> Value<'f>;
+
+// Builds fn(input_type) -> ret_type { op(arg0) } and calls it with input
+#[cfg(test)]
+fn run_unary<ArgT: JitPrimitive, RetT: JitPrimitive>(op: UnaryOp, input: ArgT) -> RetT {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, RetT::jit_type(), vec![ArgT::jit_type()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let res = op(func, &x);
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    let result: extern "C" fn(ArgT) -> RetT = func.to_closure().unwrap();
+    result(input)
+}
+
+// Builds fn(a_type, b_type) -> ret_type { op(arg0, arg1) } and calls it with a and b. The
+// operands are args so libjit can't fold the op away at build time.
+#[cfg(test)]
+fn run_binary<A: JitPrimitive, B: JitPrimitive, RetT: JitPrimitive>(op: BinaryOp, a: A, b: B) -> RetT {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, RetT::jit_type(), vec![A::jit_type(), B::jit_type()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let y = func.arg(1).unwrap();
+        let res = op(func, &x, &y);
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    let result: extern "C" fn(A, B) -> RetT = func.to_closure().unwrap();
+    result(a, b)
+}
+
+#[test]
+fn test_math_unary() {
+    let cases: Vec<(UnaryOp, f64, f64)> = vec![
+        (|f, v| f.insn_sqrt(v), 16.0, 4.0),
+        (|f, v| f.insn_sin(v), 0.0, 0.0),
+        (|f, v| f.insn_cos(v), 0.0, 1.0),
+        (|f, v| f.insn_tan(v), 0.0, 0.0),
+        (|f, v| f.insn_asin(v), 0.0, 0.0),
+        (|f, v| f.insn_acos(v), 1.0, 0.0),
+        (|f, v| f.insn_atan(v), 0.0, 0.0),
+        (|f, v| f.insn_sinh(v), 0.0, 0.0),
+        (|f, v| f.insn_cosh(v), 0.0, 1.0),
+        (|f, v| f.insn_tanh(v), 0.0, 0.0),
+        (|f, v| f.insn_exp(v), 0.0, 1.0),
+        (|f, v| f.insn_log(v), std::f64::consts::E, 1.0),
+        (|f, v| f.insn_log10(v), 1000.0, 3.0),
+        (|f, v| f.insn_floor(v), -1.5, -2.0),
+        (|f, v| f.insn_ceil(v), -1.5, -1.0),
+        (|f, v| f.insn_round(v), 2.5, 3.0),
+        (|f, v| f.insn_rint(v), 2.5, 2.0),
+        (|f, v| f.insn_trunc(v), -1.7, -1.0),
+    ];
+    for (op, input, expected) in cases {
+        let result: f64 = run_unary(op, input);
+        assert!((result - expected).abs() < 1e-12, "expected {} got {}", expected, result);
+    }
+}
+
+#[test]
+fn test_math_unary_float32() {
+    let result: f32 = run_unary(|f, v| f.insn_sqrt(v), 2.25f32);
+    assert_eq!(result, 1.5);
+    let result: f32 = run_unary(|f, v| f.insn_floor(v), -0.5f32);
+    assert_eq!(result, -1.0);
+}
+
+#[test]
+fn test_float_classification() {
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_is_nan(v), f64::NAN), 1);
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_is_nan(v), 1.0), 0);
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_is_inf(v), f64::INFINITY), 1);
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_is_inf(v), f64::NEG_INFINITY), -1);
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_is_inf(v), 1.0), 0);
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_is_finite(v), 1.0), 1);
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_is_finite(v), f64::NAN), 0);
+}
+
+#[test]
+fn test_pow_and_atan2() {
+    let pow: BinaryOp = |f, a, b| f.insn_pow(a, b);
+    let atan2: BinaryOp = |f, a, b| f.insn_atan2(a, b);
+    assert_eq!(run_binary::<f64, f64, f64>(pow, 2.0, 10.0), 1024.0);
+    assert_eq!(run_binary::<f64, f64, f64>(atan2, 1.0, -1.0), 1.0f64.atan2(-1.0));
+}
+
+#[test]
+fn test_convert_float_to_int_truncates() {
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_to_int(v), 3.9), 3);
+    assert_eq!(run_unary::<f64, i32>(|f, v| f.insn_to_int(v), -3.9), -3);
+}
+
+#[test]
+fn test_convert_widen() {
+    assert_eq!(run_unary::<u8, i64>(|f, v| f.insn_to_long(v), 200), 200);
+    assert_eq!(run_unary::<i8, i64>(|f, v| f.insn_to_long(v), -100), -100);
+    assert_eq!(run_unary::<i32, f64>(|f, v| f.insn_to_float64(v), -7), -7.0);
+}
+
+#[test]
+fn test_convert_narrow_wraps_without_check() {
+    assert_eq!(run_unary::<i32, u8>(|f, v| f.insn_to_ubyte(v), 257), 1);
+    assert_eq!(run_unary::<i64, i32>(|f, v| f.insn_to_int(v), 1 << 32), 0);
+}
+
+#[test]
+fn test_checked_convert_in_range() {
+    let to_ubyte_checked: UnaryOp = |f, v| f.insn_convert(v, Context::ubyte_type(), true);
+    assert_eq!(run_unary::<i32, u8>(to_ubyte_checked, 255), 255);
+    assert_eq!(run_unary::<i32, u8>(to_ubyte_checked, 0), 0);
+}
+
+#[test]
+fn test_checked_convert_out_of_range() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::ubyte_type(), vec![Context::int_type()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let res = func.insn_convert(&x, Context::ubyte_type(), true);
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let to_ubyte = func.to_compiled_fn::<(i32,), u8>().unwrap();
+    assert_eq!(to_ubyte.try_call((256,)), Err(JitRuntimeError::Overflow));
+    assert_eq!(to_ubyte.try_call((-1,)), Err(JitRuntimeError::Overflow));
+}
+
+#[test]
+fn test_recursive_call() {
+    let context = Context::new();
+    context.build_start();
+    let long_type = Context::long_type();
+    let mut func = context.function(Abi::Cdecl, long_type.clone(), vec![long_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // fact(n) = if n <= 1 { 1 } else { n * fact(n - 1) }
+        let n = func.arg(0).unwrap();
+        let one = func.create_long_constant(1);
+        let is_base = func.insn_le(&n, &one);
+        let mut recurse = Label::new();
+        func.insn_branch_if_not(&is_base, &mut recurse);
+        func.insn_return(&one);
+        func.insn_label(&mut recurse);
+        let n_minus_one = func.insn_sub(&n, &one);
+        let sub_result = func.insn_call(func, vec![n_minus_one]).unwrap();
+        let result = func.insn_mult(&n, &sub_result);
+        func.insn_return(&result);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let fact: extern "C" fn(i64) -> i64 = func.to_closure().unwrap();
+    assert_eq!(fact(1), 1);
+    assert_eq!(fact(10), 3628800);
+}
+
+#[test]
+fn test_mutual_recursion_compiled_on_demand() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut is_even = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    let mut is_odd = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    // is_even(n) = if n == 0 { 1 } else { is_odd(n - 1) }
+    // is_odd(n) = if n == 0 { 0 } else { is_even(n - 1) }
+    fn build<'ctx>(func: &mut FunctionBuilder<'ctx, '_>, other: &Function<'ctx>, base: i32) {
+        let n = func.arg(0).unwrap();
+        let zero = func.create_int_constant(0);
+        let one = func.create_int_constant(1);
+        let base = func.create_int_constant(base);
+        let is_zero = func.insn_eq(&n, &zero);
+        let mut recurse = Label::new();
+        func.insn_branch_if_not(&is_zero, &mut recurse);
+        func.insn_return(&base);
+        func.insn_label(&mut recurse);
+        let n_minus_one = func.insn_sub(&n, &one);
+        let result = func.insn_call(other, vec![n_minus_one]).unwrap();
+        func.insn_return(&result);
+    }
+    is_even.build(|func| build(func, &is_odd, 1));
+    is_odd.build(|func| build(func, &is_even, 0));
+    // Only is_even is compiled up front, is_odd is compiled when it's first called
+    is_even.compile().unwrap();
+    context.build_end();
+
+    let is_even: extern "C" fn(i32) -> i32 = is_even.to_closure().unwrap();
+    assert_eq!(is_even(10), 1);
+    assert_eq!(is_even(7), 0);
+}
+
+#[test]
+fn test_tail_call_to_self() {
+    let context = Context::new();
+    context.build_start();
+    let long_type = Context::long_type();
+    let mut func = context.function(Abi::Cdecl, long_type.clone(), vec![long_type.clone(), long_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // sum(n, acc) = if n == 0 { acc } else { sum(n - 1, acc + n) }
+        let n = func.arg(0).unwrap();
+        let acc = func.arg(1).unwrap();
+        let zero = func.create_long_constant(0);
+        let one = func.create_long_constant(1);
+        let is_zero = func.insn_eq(&n, &zero);
+        let mut recurse = Label::new();
+        func.insn_branch_if_not(&is_zero, &mut recurse);
+        func.insn_return(&acc);
+        func.insn_label(&mut recurse);
+        let n_minus_one = func.insn_sub(&n, &one);
+        let acc_plus_n = func.insn_add(&acc, &n);
+        let result = func.insn_call_tail(func, vec![n_minus_one, acc_plus_n]).unwrap();
+        func.insn_return(&result);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    // Deep enough to overflow the stack without the tail call
+    let sum: extern "C" fn(i64, i64) -> i64 = func.to_closure().unwrap();
+    assert_eq!(sum(10_000_000, 0), 50_000_005_000_000);
+}
+
+#[test]
+fn test_tail_call_signature_mismatch() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut callee = context.function(Abi::Cdecl, Context::long_type(), vec![int_type.clone()]).unwrap();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    callee.build(|callee| {
+        let x = callee.arg(0).unwrap();
+        let res = callee.insn_to_long(&x);
+        callee.insn_return(&res);
+    });
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        assert!(matches!(func.insn_call_tail(&callee, vec![x.clone()]), Err(Error::InvalidSignature(_))));
+        // A plain call is fine, the result doesn't have to be returned as is
+        assert!(func.insn_call(&callee, vec![x]).is_ok());
+    });
+}
+
+#[test]
+fn test_call_wrong_arg_count() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    func.build(|func| {
+        let one = func.create_int_constant(1);
+        assert!(matches!(func.insn_call(func, vec![one.clone(), one]), Err(Error::TypeMismatch(_))));
+    });
+}
+
+#[cfg(test)]
+extern "C" fn triple(x: i32) -> i32 {
+    x * 3
+}
+
+#[test]
+fn test_call_indirect() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![jit_void_ptr!(), int_type.clone()]).unwrap();
+    func.build(|func| {
+        let ptr = func.arg(0).unwrap();
+        let x = func.arg(1).unwrap();
+        let signature = JitType::signature(Abi::Cdecl, &int_type, std::slice::from_ref(&int_type));
+        let result = func.insn_call_indirect(&ptr, &signature, vec![x]).unwrap();
+        func.insn_return(&result);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(*mut libc::c_void, i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(triple as *mut libc::c_void, 5), 15);
+}
+
+#[test]
+fn test_call_through_vtable() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut double = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    double.build(|double| {
+        let x = double.arg(0).unwrap();
+        let res = double.insn_add(&x, &x);
+        double.insn_return(&res);
+    });
+    let mut square = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    square.build(|square| {
+        let x = square.arg(0).unwrap();
+        let res = square.insn_mult(&x, &x);
+        square.insn_return(&res);
+    });
+
+    // dispatch(idx, x) = [double, square][idx](x)
+    let mut dispatch = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone(), int_type.clone()]).unwrap();
+    dispatch.build(|dispatch| {
+        let idx = dispatch.arg(0).unwrap();
+        let x = dispatch.arg(1).unwrap();
+        let table = dispatch.alloca_of(Context::void_ptr_type(), 2).unwrap();
+        let zero = dispatch.create_int_constant(0);
+        let one = dispatch.create_int_constant(1);
+        let double_ptr = dispatch.create_vtable_pointer_constant(&double);
+        let square_ptr = dispatch.create_vtable_pointer_constant(&square);
+        dispatch.insn_store_elem(&table, &zero, &double_ptr);
+        dispatch.insn_store_elem(&table, &one, &square_ptr);
+        let target = dispatch.insn_load_elem(&table, &idx, jit_void_ptr!());
+        let signature = JitType::signature(Abi::Cdecl, &int_type, std::slice::from_ref(&int_type));
+        let result = dispatch.insn_call_indirect_vtable(&target, &signature, vec![x]).unwrap();
+        dispatch.insn_return(&result);
+    });
+    dispatch.compile().unwrap();
+    context.build_end();
+
+    let dispatch: extern "C" fn(i32, i32) -> i32 = dispatch.to_closure().unwrap();
+    assert_eq!(dispatch(0, 7), 14);
+    assert_eq!(dispatch(1, 7), 49);
+}
+
+#[cfg(test)]
+extern "C" fn scale(x: i64, factor: f64) -> i32 {
+    (x as f64 * factor) as i32
+}
+
+#[test]
+fn test_call_rust_converts_args() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+    func.build(|func| {
+        // Both are ints, scale takes an i64 and an f64
+        let x = func.create_int_constant(10);
+        let factor = func.create_int_constant(3);
+        let result = func.insn_call_rust_named("scale", scale as extern "C" fn(i64, f64) -> i32, vec![x, factor]).unwrap();
+        func.insn_return(&result);
+        assert!(func.dump().unwrap().contains("scale"));
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn() -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(), 30);
+}
+
+#[test]
+fn test_call_rust_wrong_arg_count() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+    func.build(|func| {
+        let x = func.create_int_constant(10);
+        let result = func.insn_call_rust::<extern "C" fn(i64, f64) -> i32>(scale, vec![x]);
+        assert!(matches!(result, Err(Error::TypeMismatch(_))));
+    });
+}
+
+#[cfg(test)]
+#[repr(C)]
+struct Record {
+    id: u8,
+    value: f64,
+    count: i32,
+}
+
+#[test]
+fn test_struct_layout_matches_repr_c() {
+    use crate::StructBuilder;
+    let record = StructBuilder::new()
+        .field("id", Context::ubyte_type())
+        .field("value", Context::float64_type())
+        .field("count", Context::int_type())
+        .build()
+        .unwrap();
+    assert_eq!(record.num_fields(), 3);
+    assert_eq!(record.offset("id"), Some(std::mem::offset_of!(Record, id)));
+    assert_eq!(record.offset("value"), Some(std::mem::offset_of!(Record, value)));
+    assert_eq!(record.offset("count"), Some(std::mem::offset_of!(Record, count)));
+    assert_eq!(record.size(), std::mem::size_of::<Record>());
+    assert_eq!(record.alignment(), std::mem::align_of::<Record>());
+    assert_eq!(record.offset("missing"), None);
+}
+
+#[test]
+fn test_union_layout() {
+    use crate::StructBuilder;
+    let union = StructBuilder::new()
+        .field("small", Context::ubyte_type())
+        .field("big", Context::float64_type())
+        .build_union()
+        .unwrap();
+    assert_eq!(union.offset("small"), Some(0));
+    assert_eq!(union.offset("big"), Some(0));
+    assert_eq!(union.size(), 8);
+}
+
+#[test]
+fn test_struct_duplicate_field() {
+    use crate::StructBuilder;
+    let result = StructBuilder::new()
+        .field("a", Context::int_type())
+        .field("a", Context::int_type())
+        .build();
+    assert!(matches!(result, Err(Error::InvalidType(_))));
+}
+
+#[test]
+fn test_struct_field_access_by_name() {
+    let record_type = crate::jit_struct!(Record { id: u8, value: f64, count: i32 }).unwrap();
+    assert_eq!(record_type.size(), std::mem::size_of::<Record>());
+
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::float64_type(), vec![jit_void_ptr!()]).unwrap();
+
+    func.build(|func| {
+        // record.count += 1; return record.value * record.id
+        let ptr = func.arg(0).unwrap();
+        let count = func.insn_load_field(&ptr, &record_type, "count").unwrap();
+        let one = func.create_int_constant(1);
+        let count_plus_one = func.insn_add(&count, &one);
+        func.insn_store_field(&ptr, &record_type, "count", &count_plus_one).unwrap();
+        let id = func.insn_load_field(&ptr, &record_type, "id").unwrap();
+        let value = func.insn_load_field(&ptr, &record_type, "value").unwrap();
+        let id = func.insn_to_float64(&id);
+        let result = func.insn_mult(&value, &id);
+        func.insn_return(&result);
+        assert!(matches!(func.insn_load_field(&ptr, &record_type, "nope"), Err(Error::UnknownField(_))));
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let mut record = Record { id: 3, value: 1.5, count: 41 };
+    let result: extern "C" fn(*mut Record) -> f64 = func.to_closure().unwrap();
+    assert_eq!(result(&mut record), 4.5);
+    assert_eq!(record.count, 42);
+}
+
+#[test]
+fn test_pointer_type_param() {
+    let context = Context::new();
+    context.build_start();
+    let float64_type = Context::float64_type();
+    let mut func = context.function(Abi::Cdecl, float64_type.clone(), vec![float64_type.pointer_to()]).unwrap();
+    func.build(|func| {
+        let ptr = func.arg(0).unwrap();
+        let value = func.insn_load_relative(&ptr, 8, float64_type.clone());
+        func.insn_return(&value);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let values = [1.5f64, 2.5];
+    let result: extern "C" fn(*const f64) -> f64 = func.to_closure().unwrap();
+    assert_eq!(result(values.as_ptr()), 2.5);
+}
+
+#[test]
+fn test_tagged_types() {
+    let int_type = Context::int_type();
+    assert_eq!(int_type.tagged_kind(), None);
+    assert_eq!(int_type.name(), None);
+
+    let tagged = int_type.tagged(20000);
+    assert_eq!(tagged.tagged_kind(), Some(20000));
+    assert_eq!(tagged.name(), None);
+
+    let named = int_type.named("my_int").unwrap();
+    let copy = named.clone();
+    drop(named);
+    assert_eq!(copy.name(), Some("my_int".to_string()));
+    assert!(matches!(int_type.named("my\0int"), Err(Error::InvalidType(_))));
+}
+
+#[test]
+fn test_signature_outlives_clones() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![jit_void_ptr!(), int_type.clone()]).unwrap();
+    func.build(|func| {
+        let ptr = func.arg(0).unwrap();
+        let x = func.arg(1).unwrap();
+        let signature = JitType::signature(Abi::Cdecl, &int_type, std::slice::from_ref(&int_type));
+        let first = func.insn_call_indirect(&ptr, &signature.clone(), vec![x]).unwrap();
+        let second = func.insn_call_indirect(&ptr, &signature, vec![first]).unwrap();
+        drop(signature);
+        func.insn_return(&second);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(*mut libc::c_void, i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(triple as *mut libc::c_void, 2), 18);
+}
+
+#[test]
+fn test_type_kind_and_size() {
+    let int_type = Context::int_type();
+    assert_eq!(int_type.kind(), TypeKind::Int);
+    assert_eq!(int_type.size(), 4);
+    assert!(int_type.is_primitive());
+    assert!(!int_type.is_pointer());
+
+    let ptr_type = Context::float64_type().pointer_to();
+    assert_eq!(ptr_type.kind(), TypeKind::Ptr);
+    assert_eq!(ptr_type.size(), std::mem::size_of::<*const f64>());
+    assert!(ptr_type.is_pointer());
+    assert!(!ptr_type.is_primitive());
+    assert_eq!(ptr_type.pointee(), Some(Context::float64_type()));
+    assert_eq!(int_type.pointee(), None);
+
+    assert_eq!(int_type.named("my_int").unwrap().kind(), TypeKind::Tagged(JIT_TYPETAG_NAME as i32));
+}
+
+#[test]
+fn test_signature_introspection() {
+    let sig = JitType::signature(Abi::Cdecl, &Context::long_type(), &[Context::int_type(), Context::float64_type()]);
+    assert_eq!(sig.kind(), TypeKind::Signature);
+    assert_eq!(sig.num_params(), 2);
+    assert_eq!(sig.param(1), Some(Context::float64_type()));
+    assert_eq!(sig.param(2), None);
+    assert_eq!(sig.return_type(), Some(Context::long_type()));
+    assert_eq!(Context::int_type().return_type(), None);
+    assert_eq!(Context::int_type().num_params(), 0);
+}
+
+#[test]
+fn test_type_equality() {
+    assert_eq!(Context::int_type(), Context::int_type());
+    assert_ne!(Context::int_type(), Context::long_type());
+    assert_eq!(Context::int_type().pointer_to(), Context::int_type().pointer_to());
+    assert_ne!(Context::int_type().pointer_to(), Context::long_type().pointer_to());
+
+    let make_sig = |ret: JitType| JitType::signature(Abi::Cdecl, &ret, &[Context::int_type()]);
+    assert_eq!(make_sig(Context::int_type()), make_sig(Context::int_type()));
+    assert_ne!(make_sig(Context::int_type()), make_sig(Context::float64_type()));
+
+    assert_eq!(Context::int_type().named("a").unwrap(), Context::int_type().named("a").unwrap());
+    assert_ne!(Context::int_type().named("a").unwrap(), Context::int_type().named("b").unwrap());
+    assert_ne!(Context::int_type().named("a").unwrap(), Context::int_type());
+}
+
+#[test]
+fn test_value_type_introspection() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![Context::float64_type()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        assert_eq!(x.value_type(), Context::float64_type());
+        let converted = func.insn_to_int(&x);
+        assert_eq!(converted.value_type().kind(), TypeKind::Int);
+        func.insn_return(&converted);
+    });
+    context.build_end();
+    func.compile().unwrap();
+    assert_eq!(func.to_closure::<extern "C" fn(f64) -> i32>().unwrap()(7.9), 7);
+}
+
+#[test]
+fn test_type_display() {
+    assert_eq!(Context::int_type().to_string(), "int");
+    assert_eq!(Context::float64_type().pointer_to().pointer_to().to_string(), "float64**");
+    let sig = JitType::signature(Abi::Cdecl, &Context::long_type(), &[Context::int_type(), Context::ubyte_type()]);
+    assert_eq!(sig.to_string(), "long(int, ubyte)");
+    assert_eq!(Context::int_type().named("my_int").unwrap().to_string(), "my_int");
+    let record_type = crate::jit_struct!(Record { id: u8, value: f64, count: i32 }).unwrap();
+    assert_eq!(record_type.jit_type().to_string(), "struct { ubyte; float64; int; }");
+}
+
+#[cfg(test)]
+fn return_constant<T: JitPrimitive + Debug + PartialEq + 'static>(value: T) {
+    let test = move |func: &mut FunctionBuilder, _context: &Context| {
+        let constant = func.create_constant(value);
+        assert_eq!(constant.value_type(), T::jit_type());
+        func.insn_return(&constant);
+    };
+    make_test(Box::new(test), value, T::jit_type());
+}
+
+#[test]
+fn test_create_constant_every_primitive() {
+    return_constant(-5i8);
+    return_constant(250u8);
+    return_constant(-30000i16);
+    return_constant(65000u16);
+    return_constant(-2_000_000_000i32);
+    return_constant(4_000_000_000u32);
+    return_constant(-(1i64 << 40));
+    return_constant(u64::MAX - 1);
+    return_constant(isize::MIN + 1);
+    return_constant(usize::MAX - 1);
+    return_constant(1.5f32);
+    return_constant(-2.25f64);
+    return_constant(0x1234 as *mut libc::c_void);
+}
+
+#[test]
+fn test_typed_constants() {
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
+        let a = func.create_float32_constant(1.5);
+        assert_eq!(a.value_type(), Context::float32_type());
+        func.insn_return(&a);
+    };
+    make_test(Box::new(test), 1.5f32, Context::float32_type());
+
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
+        let a = func.create_short_constant(-7);
+        let b = func.create_ushort_constant(9);
+        assert_eq!(a.value_type(), Context::short_type());
+        assert_eq!(b.value_type(), Context::ushort_type());
+        let res = func.insn_add(&a, &b);
+        func.insn_return(&res);
+    };
+    make_test(Box::new(test), 2, Context::int_type());
+
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
+        let a = func.create_ulong_constant(u64::MAX);
+        func.insn_return(&a);
+    };
+    make_test(Box::new(test), u64::MAX, Context::ulong_type());
+
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
+        let a = func.create_nuint_constant(42);
+        assert_eq!(a.value_type(), Context::nuint_type());
+        func.insn_return(&a);
+    };
+    make_test(Box::new(test), 42usize, Context::nuint_type());
+
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
+        let a = func.create_nfloat_constant(2.5);
+        assert_eq!(a.value_type(), Context::nfloat_type());
+        let res = func.insn_to_float64(&a);
+        func.insn_return(&res);
+    };
+    make_test(Box::new(test), 2.5f64, Context::float64_type());
+}
+
+#[test]
+fn test_primitive_types() {
+    assert_eq!(Context::short_type().kind(), TypeKind::Short);
+    assert_eq!(Context::ushort_type().kind(), TypeKind::UShort);
+    assert_eq!(Context::uint_type().kind(), TypeKind::UInt);
+    assert_eq!(Context::ulong_type().kind(), TypeKind::ULong);
+    assert_eq!(Context::nint_type().kind(), TypeKind::NInt);
+    assert_eq!(Context::nuint_type().kind(), TypeKind::NUInt);
+    assert_eq!(Context::nfloat_type().kind(), TypeKind::NFloat);
+    assert_eq!(Context::void_type().kind(), TypeKind::Void);
+    assert!(Context::void_ptr_type().is_pointer());
+    assert_eq!(Context::nuint_type().size(), std::mem::size_of::<usize>());
+}
+
+#[test]
+fn test_local_in_loop() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // sum = 0; i = 0; while i < n { i += 1; sum += i; } return sum
+        let n = func.arg(0).unwrap();
+        let sum = func.create_local(int_type.clone());
+        let i = func.create_local(int_type.clone());
+        assert_eq!(i.value_type(), int_type);
+        let zero = func.create_int_constant(0);
+        let one = func.create_int_constant(1);
+        func.insn_store(&sum, &zero);
+        func.insn_store(&i, &zero);
+        let mut loop_start = Label::new();
+        let mut loop_end = Label::new();
+        func.insn_label(&mut loop_start);
+        let i_lt_n = func.insn_lt(&i, &n);
+        func.insn_branch_if_not(&i_lt_n, &mut loop_end);
+        let i_plus_one = func.insn_add(&i, &one);
+        func.insn_store(&i, &i_plus_one);
+        let new_sum = func.insn_add(&sum, &i);
+        func.insn_store(&sum, &new_sum);
+        func.insn_branch(&mut loop_start);
+        func.insn_label(&mut loop_end);
+        func.insn_return(&sum);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(0), 0);
+    assert_eq!(result(10), 55);
+}
+
+#[test]
+fn test_local_joins_branches() {
+    let context = Context::new();
+    context.build_start();
+    let float64_type = Context::float64_type();
+    let mut func = context.function(Abi::Cdecl, float64_type.clone(), vec![float64_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // if x > 0 { r = x * 2 } else { r = 0 - x } return r
+        let x = func.arg(0).unwrap();
+        let r = func.create_local(float64_type.clone());
+        let zero = func.create_float64_constant(0.0);
+        let two = func.create_float64_constant(2.0);
+        let mut else_label = Label::new();
+        let mut done_label = Label::new();
+        let x_gt_zero = func.insn_gt(&x, &zero);
+        func.insn_branch_if_not(&x_gt_zero, &mut else_label);
+        let doubled = func.insn_mult(&x, &two);
+        func.insn_store(&r, &doubled);
+        func.insn_branch(&mut done_label);
+        func.insn_label(&mut else_label);
+        let negated = func.insn_sub(&zero, &x);
+        func.insn_store(&r, &negated);
+        func.insn_label(&mut done_label);
+        func.insn_return(&r);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(f64) -> f64 = func.to_closure().unwrap();
+    assert_eq!(result(1.5), 3.0);
+    assert_eq!(result(-4.0), 4.0);
+}
+
+#[test]
+fn test_alloca_runtime_size() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // A buffer of n ints, larger than a ubyte could describe. Write n-1 to the last slot and read it back.
+        let n = func.arg(0).unwrap();
+        let four = func.create_int_constant(4);
+        let one = func.create_int_constant(1);
+        let size = func.insn_mult(&n, &four);
+        let buffer = func.alloca(&size);
+        let last = func.insn_sub(&n, &one);
+        func.insn_store_elem(&buffer, &last, &last);
+        let res = func.insn_load_elem(&buffer, &last, int_type.clone());
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(1000), 999);
+}
+
+#[test]
+fn test_alloca_of() {
+    let test = |func: &mut FunctionBuilder, _context: &Context| {
+        // 37 longs is 296 bytes, store in the last one
+        let buffer = func.alloca_of(Context::long_type(), 37).unwrap();
+        let value = func.create_long_constant(77);
+        func.insn_store_relative(&buffer, 36 * 8, &value);
+        let res = func.insn_load_relative(&buffer, 36 * 8, Context::long_type());
+        func.insn_return(&res);
+    };
+    make_test(Box::new(test), 77i64, Context::long_type());
+}
+
+#[test]
+fn test_alloca_of_size_overflow() {
+    let context = Context::new();
+    let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+    func.build(|func| {
+        assert!(matches!(func.alloca_of(Context::long_type(), usize::MAX / 4), Err(Error::SizeOverflow(_))));
+    });
+}
+
+#[test]
+fn test_memcpy_memmove_memset() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::void_type(), vec![jit_void_ptr!(), jit_void_ptr!(), jit_int!()]).unwrap();
+
+    func.build(|func| {
+        // memset(dest, 7, n); memcpy(dest, src, 4); memmove(dest + 1, dest, 4)
+        let dest = func.arg(0).unwrap();
+        let src = func.arg(1).unwrap();
+        let n = func.arg(2).unwrap();
+        let seven = func.create_int_constant(7);
+        let four = func.create_int_constant(4);
+        let one = func.create_nint_constant(1);
+        func.insn_memset(&dest, &seven, &n);
+        func.insn_memcpy(&dest, &src, &four);
+        let dest_plus_one = func.insn_add(&dest, &one);
+        func.insn_memmove(&dest_plus_one, &dest, &four);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let mut dest = [0u8; 8];
+    let src = [1u8, 2, 3, 4];
+    let result: extern "C" fn(*mut u8, *const u8, i32) = func.to_closure().unwrap();
+    result(dest.as_mut_ptr(), src.as_ptr(), 8);
+    assert_eq!(dest, [1, 1, 2, 3, 4, 7, 7, 7]);
+}
+
+#[test]
+fn test_copy_and_zero_struct() {
+    let record_type = crate::jit_struct!(Record { id: u8, value: f64, count: i32 }).unwrap();
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::void_type(), vec![jit_void_ptr!(), jit_void_ptr!()]).unwrap();
+    func.build(|func| {
+        let dest = func.arg(0).unwrap();
+        let src = func.arg(1).unwrap();
+        func.insn_copy_struct(&dest, &src, &record_type);
+        func.insn_zero_struct(&src, &record_type);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let mut dest = Record { id: 0, value: 0.0, count: 0 };
+    let mut src = Record { id: 9, value: 2.5, count: -3 };
+    let result: extern "C" fn(*mut Record, *mut Record) = func.to_closure().unwrap();
+    result(&mut dest, &mut src);
+    assert_eq!((dest.id, dest.value, dest.count), (9, 2.5, -3));
+    assert_eq!((src.id, src.value, src.count), (0, 0.0, 0));
+}
+
+#[test]
+fn test_if_else() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone(), int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // Same as examples/branch_with_result.rs
+        let a = func.arg(0).unwrap();
+        let b = func.arg(1).unwrap();
+        let result = func.create_local(int_type.clone());
+        let a_eq_b = func.insn_eq(&a, &b);
+        func.if_else(&a_eq_b, |f| {
+            let sum = f.insn_add(&a, &b);
+            f.insn_store(&result, &sum);
+        }, |f| {
+            let diff = f.insn_sub(&a, &b);
+            f.insn_store(&result, &diff);
+        });
+        let hundred = func.create_int_constant(100);
+        let ret = func.insn_add(&result, &hundred);
+        func.insn_return(&ret);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i32, i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(1, 1), 102);
+    assert_eq!(result(3, 2), 101);
+}
+
+#[test]
+fn test_if_then() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // if x < 0 { return 0 } return x
+        let x = func.arg(0).unwrap();
+        let zero = func.create_int_constant(0);
+        let negative = func.insn_lt(&x, &zero);
+        func.if_then(&negative, |f| f.insn_return(&zero));
+        func.insn_return(&x);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(-4), 0);
+    assert_eq!(result(4), 4);
+}
+
+#[test]
+fn test_while_loop_break_continue() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // Sum the odd numbers below n, stopping once the sum passes 50
+        let n = func.arg(0).unwrap();
+        let i = func.create_local(int_type.clone());
+        let sum = func.create_local(int_type.clone());
+        let zero = func.create_int_constant(0);
+        let one = func.create_int_constant(1);
+        let two = func.create_int_constant(2);
+        let fifty = func.create_int_constant(50);
+        func.insn_store(&i, &zero);
+        func.insn_store(&sum, &zero);
+        func.while_loop(|f| f.insn_lt(&i, &n), |f, lp: Loop| {
+            let current = f.create_local(Context::int_type());
+            f.insn_store(&current, &i);
+            let i_plus_one = f.insn_add(&i, &one);
+            f.insn_store(&i, &i_plus_one);
+            let rem = f.insn_rem(&current, &two);
+            let is_even = f.insn_eq(&rem, &zero);
+            f.if_then(&is_even, |f| f.continue_(&lp));
+            let new_sum = f.insn_add(&sum, &current);
+            f.insn_store(&sum, &new_sum);
+            let done = f.insn_gt(&sum, &fifty);
+            f.if_then(&done, |f| f.break_(&lp));
+        });
+        func.insn_return(&sum);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(result(0), 0);
+    assert_eq!(result(6), 1 + 3 + 5);
+    assert_eq!(result(100), 1 + 3 + 5 + 7 + 9 + 11 + 13 + 15);
+}
+
+#[test]
+fn test_loop_with_break() {
+    let context = Context::new();
+    context.build_start();
+    let long_type = Context::long_type();
+    let mut func = context.function(Abi::Cdecl, long_type.clone(), vec![long_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // Smallest power of two >= x
+        let x = func.arg(0).unwrap();
+        let power = func.create_local(long_type.clone());
+        let one = func.create_long_constant(1);
+        func.insn_store(&power, &one);
+        func.loop_(|f, lp| {
+            let big_enough = f.insn_ge(&power, &x);
+            f.if_then(&big_enough, |f| f.break_(&lp));
+            let doubled = f.insn_shl(&power, &one);
+            f.insn_store(&power, &doubled);
+        });
+        func.insn_return(&power);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result: extern "C" fn(i64) -> i64 = func.to_closure().unwrap();
+    assert_eq!(result(1), 1);
+    assert_eq!(result(5), 8);
+    assert_eq!(result(1 << 40), 1 << 40);
+}
+
+#[test]
+fn test_unplaced_label_rejected() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let mut never_placed = Label::new();
+        func.insn_branch_if(&x, &mut never_placed);
+        func.insn_return(&x);
+    });
+    assert!(matches!(func.compile(), Err(Error::UnplacedLabel(_))));
+    context.build_end();
+}
+
+#[test]
+fn test_unplaced_label_rejected_on_demand() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut callee = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    callee.build(|callee| {
+        let x = callee.arg(0).unwrap();
+        let mut never_placed = Label::new();
+        callee.insn_branch_if(&x, &mut never_placed);
+        callee.insn_return(&x);
+    });
+    // callee is never compiled explicitly, it's compiled when func first calls it
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let res = func.insn_call(&callee, vec![x]).unwrap();
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result = func.to_compiled_fn::<(i32,), i32>().unwrap();
+    assert_eq!(result.try_call((1,)), Err(JitRuntimeError::CompileError));
+}
+
+#[test]
+fn test_try_catch_handles_throw() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // try { if x > 10 { throw 99 } return x } catch e { return e + 1 }
+        let x = func.arg(0).unwrap();
+        let ten = func.create_int_constant(10);
+        func.try_catch(|f| {
+            let too_big = f.insn_gt(&x, &ten);
+            f.if_then(&too_big, |f| {
+                let thrown = f.create_void_ptr_constant(99 as *mut libc::c_void);
+                f.insn_throw(&thrown);
+            });
+            f.insn_return(&x);
+        }, |f, exception| {
+            let code = f.insn_to_int(&exception);
+            let one = f.create_int_constant(1);
+            let ret = f.insn_add(&code, &one);
+            f.insn_return(&ret);
+        });
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result = func.to_compiled_fn::<(i32,), i32>().unwrap();
+    assert_eq!(result.try_call((5,)), Ok(5));
+    assert_eq!(result.try_call((50,)), Ok(100));
+}
+
+#[test]
+fn test_uncaught_throw_is_an_err() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let zero = func.create_int_constant(0);
+        let negative = func.insn_lt(&x, &zero);
+        func.if_then(&negative, |f| {
+            let thrown = f.create_void_ptr_constant(7 as *mut libc::c_void);
+            f.insn_throw(&thrown);
+        });
+        func.insn_return(&x);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result = func.to_compiled_fn::<(i32,), i32>().unwrap();
+    assert_eq!(result.try_call((3,)), Ok(3));
+    assert_eq!(result.try_call((-3,)), Err(JitRuntimeError::Thrown(7)));
+    assert_eq!(result.call((4,)), 4);
+}
+
+#[test]
+#[should_panic(expected = "Uncaught exception in jit'ed function")]
+fn test_call_panics_on_uncaught_throw() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![]).unwrap();
+    func.build(|func| {
+        let thrown = func.create_void_ptr_constant(7 as *mut libc::c_void);
+        func.insn_throw(&thrown);
+        let zero = func.create_int_constant(0);
+        func.insn_return(&zero);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    func.to_compiled_fn::<(), i32>().unwrap().call(());
+}
+
+#[test]
+fn test_errors_are_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Error>();
+    assert_send_sync::<JitRuntimeError>();
+}
+
+#[test]
+fn test_nested_try_catch_and_rethrow() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // try { try { throw x } catch e { if e == 1 { return 10 } throw e + 100 } } catch e { return e }
+        let x = func.arg(0).unwrap();
+        func.try_catch(|f| {
+            f.try_catch(|f| {
+                f.insn_throw(&x);
+            }, |f, exception| {
+                let code = f.insn_to_int(&exception);
+                let one = f.create_int_constant(1);
+                let is_one = f.insn_eq(&code, &one);
+                f.if_then(&is_one, |f| {
+                    let ten = f.create_int_constant(10);
+                    f.insn_return(&ten);
+                });
+                let hundred = f.create_int_constant(100);
+                let rethrown = f.insn_add(&code, &hundred);
+                f.insn_throw(&rethrown);
+            });
+        }, |f, exception| {
+            let code = f.insn_to_int(&exception);
+            f.insn_return(&code);
+        });
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result = func.to_compiled_fn::<(i32,), i32>().unwrap();
+    assert_eq!(result.try_call((1,)), Ok(10));
+    assert_eq!(result.try_call((2,)), Ok(102));
+}
+
+#[test]
+fn test_catch_exception_from_callee() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut thrower = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    thrower.build(|thrower| {
+        let x = thrower.arg(0).unwrap();
+        thrower.insn_throw(&x);
+    });
+    thrower.compile().unwrap();
+
+    // Not compiled explicitly, the catcher has to be emitted by on-demand compilation
+    let mut catcher = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    catcher.build(|catcher| {
+        let x = catcher.arg(0).unwrap();
+        catcher.try_catch(|f| {
+            let res = f.insn_call(&thrower, vec![x.clone()]).unwrap();
+            f.insn_return(&res);
+        }, |f, exception| {
+            let code = f.insn_to_int(&exception);
+            let two = f.create_int_constant(2);
+            let ret = f.insn_mult(&code, &two);
+            f.insn_return(&ret);
+        });
+    });
+    context.build_end();
+
+    let result = catcher.to_compiled_fn::<(i32,), i32>().unwrap();
+    assert_eq!(result.try_call((21,)), Ok(42));
+}
+
+#[test]
+fn test_builtin_exceptions_become_errors() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut div = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone(), int_type.clone()]).unwrap();
+    div.build(|div| {
+        let a = div.arg(0).unwrap();
+        let b = div.arg(1).unwrap();
+        let res = div.insn_div(&a, &b);
+        div.insn_return(&res);
+    });
+    div.compile().unwrap();
+
+    let mut narrow = context.function(Abi::Cdecl, Context::ubyte_type(), vec![int_type.clone()]).unwrap();
+    narrow.build(|narrow| {
+        let x = narrow.arg(0).unwrap();
+        let res = narrow.insn_convert(&x, Context::ubyte_type(), true);
+        narrow.insn_return(&res);
+    });
+    narrow.compile().unwrap();
+    context.build_end();
+
+    let div = div.to_compiled_fn::<(i32, i32), i32>().unwrap();
+    assert_eq!(div.try_call((9, 3)), Ok(3));
+    assert_eq!(div.try_call((9, 0)), Err(JitRuntimeError::DivisionByZero));
+    assert_eq!(div.try_call((i32::MIN, -1)), Err(JitRuntimeError::Arithmetic));
+    let narrow = narrow.to_compiled_fn::<(i32,), u8>().unwrap();
+    assert_eq!(narrow.try_call((200,)), Ok(200));
+    assert_eq!(narrow.try_call((300,)), Err(JitRuntimeError::Overflow));
+    assert_eq!(JitRuntimeError::DivisionByZero.to_string(), "Division by zero");
+}
+
+#[test]
+fn test_catch_builtin_exception() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone(), int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // try { return a / b } catch { return -1 }
+        let a = func.arg(0).unwrap();
+        let b = func.arg(1).unwrap();
+        func.try_catch(|f| {
+            let res = f.insn_div(&a, &b);
+            f.insn_return(&res);
+        }, |f, _exception| {
+            let minus_one = f.create_int_constant(-1);
+            f.insn_return(&minus_one);
+        });
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result = func.to_compiled_fn::<(i32, i32), i32>().unwrap();
+    assert_eq!(result.try_call((8, 2)), Ok(4));
+    assert_eq!(result.try_call((8, 0)), Ok(-1));
+    // The builtin handler is installed by Context::new, not by try_call
+    assert_eq!(result.call((8, 0)), -1);
+    let div: extern "C" fn(i32, i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(div(8, 0), -1);
+}
+
+#[test]
+fn test_unknown_builtin_exception() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::int_type(), vec![Context::int_type()]).unwrap();
+    func.build(|func| {
+        // Raise whatever builtin code we're given
+        let code = func.arg(0).unwrap();
+        func.insn_call_native(jit_exception_builtin as *mut libc::c_void, vec![code], None);
+        let zero = func.create_int_constant(0);
+        func.insn_return(&zero);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let result = func.to_compiled_fn::<(i32,), i32>().unwrap();
+    assert_eq!(result.try_call((12345,)), Err(JitRuntimeError::Unknown(12345)));
+    assert_eq!(result.try_call((12345,)), Err(JitRuntimeError::Unknown(12345)));
+    assert_eq!(result.try_call((-12345,)), Err(JitRuntimeError::Unknown(-12345)));
+    assert_eq!(result.try_call((JIT_RESULT_NULL_FUNCTION,)), Err(JitRuntimeError::NullFunction));
+}
+
+#[test]
+fn test_check_null() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![jit_void_ptr!()]).unwrap();
+    func.build(|func| {
+        let ptr = func.arg(0).unwrap();
+        func.insn_check_null(&ptr);
+        let value = func.insn_load_relative(&ptr, 0, int_type.clone());
+        func.insn_return(&value);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let value = 5i32;
+    let result = func.to_compiled_fn::<(*const i32,), i32>().unwrap();
+    assert_eq!(result.try_call((&value,)), Ok(5));
+    assert_eq!(result.try_call((std::ptr::null(),)), Err(JitRuntimeError::NullReference));
+}
+
+#[test]
+fn test_bounds_check() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![jit_void_ptr!(), int_type.clone(), int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // fn(array, len, index) = array[index]
+        let array = func.arg(0).unwrap();
+        let len = func.arg(1).unwrap();
+        let index = func.arg(2).unwrap();
+        func.insn_bounds_check(&index, &len);
+        let value = func.insn_load_elem(&array, &index, int_type.clone());
+        func.insn_return(&value);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let values = [10, 20, 30];
+    let result = func.to_compiled_fn::<(*const i32, i32, i32), i32>().unwrap();
+    assert_eq!(result.try_call((values.as_ptr(), 3, 0)), Ok(10));
+    assert_eq!(result.try_call((values.as_ptr(), 3, 2)), Ok(30));
+    assert_eq!(result.try_call((values.as_ptr(), 3, 3)), Err(JitRuntimeError::OutOfBounds));
+    assert_eq!(result.try_call((values.as_ptr(), 3, -1)), Err(JitRuntimeError::OutOfBounds));
+}
+
+#[test]
+fn test_catch_failed_bounds_check() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![jit_void_ptr!(), int_type.clone(), int_type.clone()]).unwrap();
+
+    func.build(|func| {
+        // fn(array, len, index) = try { array[index] } catch { -1 }
+        let array = func.arg(0).unwrap();
+        let len = func.arg(1).unwrap();
+        let index = func.arg(2).unwrap();
+        func.try_catch(|f| {
+            f.insn_bounds_check(&index, &len);
+            let value = f.insn_load_elem(&array, &index, int_type.clone());
+            f.insn_return(&value);
+        }, |f, _exception| {
+            let minus_one = f.create_int_constant(-1);
+            f.insn_return(&minus_one);
+        });
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let values = [10, 20, 30];
+    let get: extern "C" fn(*const i32, i32, i32) -> i32 = func.to_closure().unwrap();
+    assert_eq!(get(values.as_ptr(), 3, 1), 20);
+    assert_eq!(get(values.as_ptr(), 3, 3), -1);
+    assert_eq!(get(values.as_ptr(), 3, -1), -1);
+}
+
+#[test]
+fn test_runtime_checks_disabled() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![jit_void_ptr!(), int_type.clone()]).unwrap();
+    func.build(|func| {
+        func.set_runtime_checks(false);
+        let array = func.arg(0).unwrap();
+        let index = func.arg(1).unwrap();
+        let len = func.create_int_constant(1);
+        func.insn_check_null(&array);
+        func.insn_bounds_check(&index, &len);
+        let value = func.insn_load_elem(&array, &index, int_type.clone());
+        func.insn_return(&value);
+        assert!(!func.dump().unwrap().contains("check_null"));
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    // Index 1 is out of the claimed bounds but the check was compiled out
+    let values = [10, 20];
+    let result = func.to_compiled_fn::<(*const i32, i32), i32>().unwrap();
+    assert_eq!(result.try_call((values.as_ptr(), 1)), Ok(20));
+}
+
+#[cfg(test)]
+type BinaryOp = for<'ctx, 'f> fn(&mut FunctionBuilder<'ctx, 'f>, &Value<'f>, &Value<'f>) -> Value<'f>;
+
+#[cfg(test)]
+fn checked_binary<T: JitPrimitive>(op: BinaryOp, a: T, b: T) -> Result<T, JitRuntimeError> {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, T::jit_type(), vec![T::jit_type(), T::jit_type()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        let y = func.arg(1).unwrap();
+        let res = op(func, &x, &y);
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+    func.to_compiled_fn::<(T, T), T>().unwrap().try_call((a, b))
+}
+
+#[test]
+fn test_checked_arithmetic() {
+    let add: BinaryOp = |f, a, b| f.insn_add_checked(a, b);
+    let sub: BinaryOp = |f, a, b| f.insn_sub_checked(a, b);
+    let mult: BinaryOp = |f, a, b| f.insn_mult_checked(a, b);
+
+    assert_eq!(checked_binary(add, 1i32, 2), Ok(3));
+    assert_eq!(checked_binary(add, i32::MAX, 1), Err(JitRuntimeError::Overflow));
+    assert_eq!(checked_binary(sub, i32::MIN, 1), Err(JitRuntimeError::Overflow));
+    assert_eq!(checked_binary(mult, 1 << 16, 1 << 15), Err(JitRuntimeError::Overflow));
+
+    assert_eq!(checked_binary(add, u32::MAX - 1, 1), Ok(u32::MAX));
+    assert_eq!(checked_binary(add, u32::MAX, 1), Err(JitRuntimeError::Overflow));
+    assert_eq!(checked_binary(sub, 0u32, 1), Err(JitRuntimeError::Overflow));
+
+    assert_eq!(checked_binary(mult, 1i64 << 31, 1 << 31), Ok(1 << 62));
+    assert_eq!(checked_binary(mult, i64::MAX, 2), Err(JitRuntimeError::Overflow));
+    assert_eq!(checked_binary(sub, -5i64, 10), Ok(-15));
+
+    assert_eq!(checked_binary(sub, 10u64, 4), Ok(6));
+    assert_eq!(checked_binary(sub, 4u64, 10), Err(JitRuntimeError::Overflow));
+    assert_eq!(checked_binary(mult, u64::MAX, 2), Err(JitRuntimeError::Overflow));
+}
+
+#[test]
+fn test_unchecked_arithmetic_wraps() {
+    let add: BinaryOp = |f, a, b| f.insn_add(a, b);
+    assert_eq!(checked_binary(add, i32::MAX, 1), Ok(i32::MIN));
+}
+
+#[test]
+fn test_apply_dynamic_args() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::float64_type(), vec![Context::int_type(), Context::float64_type(), Context::ubyte_type()]).unwrap();
+    func.build(|func| {
+        // a * b + c
+        let a = func.arg(0).unwrap();
+        let b = func.arg(1).unwrap();
+        let c = func.arg(2).unwrap();
+        let a = func.insn_to_float64(&a);
+        let c = func.insn_to_float64(&c);
+        let product = func.insn_mult(&a, &b);
+        let res = func.insn_add(&product, &c);
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let args = [JitArg::Int(3), JitArg::Float64(1.5), JitArg::UByte(2)];
+    assert_eq!(func.apply(&args).unwrap(), JitArg::Float64(6.5));
+    let args: Vec<JitArg> = vec![4i32.into(), 0.5f64.into(), 1u8.into()];
+    assert_eq!(func.apply(&args).unwrap(), JitArg::Float64(3.0));
+}
+
+#[test]
+fn test_apply_checks_args() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::Cdecl, int_type.clone(), vec![int_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        func.insn_return(&x);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    assert_eq!(func.apply(&[JitArg::Int(7)]).unwrap(), JitArg::Int(7));
+    assert!(matches!(func.apply(&[]), Err(Error::TypeMismatch(_))));
+    assert!(matches!(func.apply(&[JitArg::Int(1), JitArg::Int(2)]), Err(Error::TypeMismatch(_))));
+    assert!(matches!(func.apply(&[JitArg::Float64(1.0)]), Err(Error::TypeMismatch(_))));
+}
+
+#[test]
+fn test_apply_returns_runtime_errors() {
+    let context = Context::new();
+    context.build_start();
+    let long_type = Context::long_type();
+    let mut func = context.function(Abi::Cdecl, long_type.clone(), vec![long_type.clone(), long_type.clone()]).unwrap();
+    func.build(|func| {
+        let a = func.arg(0).unwrap();
+        let b = func.arg(1).unwrap();
+        let res = func.insn_div(&a, &b);
+        func.insn_return(&res);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    assert_eq!(func.apply(&[JitArg::Long(10), JitArg::Long(5)]).unwrap(), JitArg::Long(2));
+    assert!(matches!(func.apply(&[JitArg::Long(10), JitArg::Long(0)]), Err(Error::Runtime(JitRuntimeError::DivisionByZero))));
+}
+
+#[test]
+fn test_apply_void_and_pointer() {
+    let context = Context::new();
+    context.build_start();
+    let mut func = context.function(Abi::Cdecl, Context::void_type(), vec![jit_void_ptr!(), jit_int!()]).unwrap();
+    func.build(|func| {
+        let ptr = func.arg(0).unwrap();
+        let x = func.arg(1).unwrap();
+        func.insn_store_relative(&ptr, 0, &x);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    let mut target = 0i32;
+    let args = [JitArg::Ptr(&mut target as *mut i32 as *mut libc::c_void), JitArg::Int(9)];
+    assert_eq!(func.apply(&args).unwrap(), JitArg::Void);
+    assert_eq!(target, 9);
+}
+
+#[test]
+fn test_apply_vararg() {
+    let context = Context::new();
+    context.build_start();
+    let int_type = Context::int_type();
+    let mut func = context.function(Abi::VarArg, int_type.clone(), vec![int_type.clone()]).unwrap();
+    func.build(|func| {
+        let x = func.arg(0).unwrap();
+        func.insn_return(&x);
+    });
+    func.compile().unwrap();
+    context.build_end();
+
+    assert_eq!(func.apply(&[JitArg::Int(3), JitArg::Float64(1.0), JitArg::Long(2)]).unwrap(), JitArg::Int(3));
+    assert!(matches!(func.apply(&[]), Err(Error::TypeMismatch(_))));
+}
@@ -1,18 +1,30 @@
-use gnu_libjit_sys::{jit_value_get_type, jit_value_t};
+use std::marker::PhantomData;
+use gnu_libjit_sys::{jit_value_get_type, jit_value_is_addressable, jit_value_set_addressable, jit_value_t};
 use crate::JitType;
 
+// A value in the IR of a single function. 'f is the brand of the FunctionBuilder that made
+// it, so it can only be used while building that function.
 #[derive(Clone)]
-pub struct Value {
+pub struct Value<'f> {
     pub(crate) value: jit_value_t,
+    _function: PhantomData<fn(&'f ()) -> &'f ()>,
 }
 
-impl Value {
-    pub(crate) fn new(value: jit_value_t) -> Value {
-        Value { value }
+impl<'f> Value<'f> {
+    pub(crate) fn new(value: jit_value_t) -> Value<'f> {
+        Value { value, _function: PhantomData }
     }
     pub fn value_type(&self) -> JitType {
-        JitType::new(unsafe {
+        unsafe { JitType::new(
             jit_value_get_type(self.value)
-        })
+       ) }
     }
-}
\ No newline at end of file
+    // Keeps the value in memory (never only in a register) so its address can be taken.
+    // insn_address_of does this for you, but it must happen before the value is first used.
+    pub fn set_addressable(&self) {
+        unsafe { jit_value_set_addressable(self.value) }
+    }
+    pub fn is_addressable(&self) -> bool {
+        unsafe { jit_value_is_addressable(self.value) != 0 }
+    }
+}